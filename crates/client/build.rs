@@ -0,0 +1,55 @@
+// embeds enough build information for `nix-btm --version --verbose` to be
+// useful in bug reports
+use std::{env, fs, path::Path, process::Command};
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() {
+    // not available when building from a source tarball (e.g. inside nix)
+    let git_commit = command_output("git", &["rev-parse", "--short", "HEAD"])
+        .unwrap_or_else(|| "unknown".to_string());
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = command_output(&rustc, &["--version"])
+        .unwrap_or_else(|| "unknown".to_string());
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|f| f.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+
+    println!("cargo:rustc-env=NIX_BTM_GIT_COMMIT={git_commit}");
+    println!("cargo:rustc-env=NIX_BTM_RUSTC_VERSION={rustc_version}");
+    println!(
+        "cargo:rustc-env=NIX_BTM_TARGET={}",
+        env::var("TARGET").unwrap_or_default()
+    );
+    println!("cargo:rustc-env=NIX_BTM_FEATURES={}", features.join(","));
+    println!("cargo:rerun-if-changed=build.rs");
+    rerun_on_new_commits(Path::new("../../.git"));
+}
+
+/// HEAD itself only changes on checkout. A commit moves the branch it
+/// names, which lives either in its own file or in packed-refs. Paths that
+/// don't exist are skipped, since cargo would rerun on every build for them
+fn rerun_on_new_commits(git_dir: &Path) {
+    let head = git_dir.join("HEAD");
+    let branch = fs::read_to_string(&head).ok().and_then(|head| {
+        head.strip_prefix("ref: ").map(|r| git_dir.join(r.trim()))
+    });
+    for path in [Some(head), branch, Some(git_dir.join("packed-refs"))]
+        .into_iter()
+        .flatten()
+    {
+        if path.exists() {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+}
@@ -0,0 +1,241 @@
+// best effort "kill this one build": signal every process a nixbld user is
+// running, and let nix notice the builder died and fail the derivation the
+// usual way. Everything here errs on the side of not signaling
+use std::{
+    collections::{BTreeSet, HashSet},
+    thread,
+    time::Duration,
+};
+
+use sysinfo::{Pid, Signal, System};
+
+use crate::get_stats::{ProcMetadata, NIX_USERS, USERS};
+
+/// how long builders get to exit on SIGTERM before we SIGKILL them
+pub const CANCEL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CancelError {
+    NotABuilder(String),
+    Idle(String),
+    /// a process we were about to signal isn't owned by the builder
+    ForeignProcess {
+        pid: Pid,
+        owner: String,
+    },
+}
+
+impl std::fmt::Display for CancelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CancelError::NotABuilder(user) => {
+                write!(f, "{user} is not a nix build user, not cancelling")
+            }
+            CancelError::Idle(user) => write!(f, "{user} isn't building"),
+            CancelError::ForeignProcess { pid, owner } => write!(
+                f,
+                "process {pid} belongs to {owner}, refusing to cancel"
+            ),
+        }
+    }
+}
+
+/// what `x` would kill, worked out up front so the user can confirm it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CancelPlan {
+    pub builder: String,
+    /// each process's pid and start time, so a pid that's been reused by
+    /// the time we signal is left alone
+    pub pids: Vec<(Pid, u64)>,
+}
+
+/// refuses anything that isn't a busy nixbld user whose processes are all
+/// its own
+pub fn plan_cancel(
+    builder: &str,
+    procs: &BTreeSet<ProcMetadata>,
+    nix_users: &HashSet<String>,
+) -> Result<CancelPlan, CancelError> {
+    if !nix_users.contains(builder) {
+        return Err(CancelError::NotABuilder(builder.to_string()));
+    }
+    if procs.is_empty() {
+        return Err(CancelError::Idle(builder.to_string()));
+    }
+    if let Some(foreign) = procs.iter().find(|p| p.owner != builder) {
+        return Err(CancelError::ForeignProcess {
+            pid: foreign.id,
+            owner: foreign.owner.clone(),
+        });
+    }
+    Ok(CancelPlan {
+        builder: builder.to_string(),
+        pids: procs.iter().map(|p| (p.id, p.start_time)).collect(),
+    })
+}
+
+pub trait SignalSender {
+    /// the user running `pid` and its start time, if it's still alive
+    fn identity(&mut self, pid: Pid) -> Option<(String, u64)>;
+    fn send(&mut self, pid: Pid, signal: Signal) -> bool;
+}
+
+/// signals every process in the plan that is still running. Pids get
+/// reused, and nixbld users go straight on to the next build, so owner and
+/// start time are both checked again right before each signal. Returns how
+/// many processes were signaled
+pub fn signal_plan(
+    plan: &CancelPlan,
+    signal: Signal,
+    sender: &mut impl SignalSender,
+) -> usize {
+    plan.pids
+        .iter()
+        .filter(|(pid, start_time)| {
+            sender.identity(*pid).is_some_and(|(owner, started)| {
+                owner == plan.builder && started == *start_time
+            }) && sender.send(*pid, signal)
+        })
+        .count()
+}
+
+pub struct SysinfoSignals(System);
+
+impl SysinfoSignals {
+    pub fn new() -> Self {
+        SysinfoSignals(System::new())
+    }
+}
+
+impl Default for SysinfoSignals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SignalSender for SysinfoSignals {
+    fn identity(&mut self, pid: Pid) -> Option<(String, u64)> {
+        if !self.0.refresh_process(pid) {
+            return None;
+        }
+        let proc = self.0.process(pid)?;
+        let user = USERS.get_user_by_id(proc.effective_user_id()?)?;
+        Some((user.name().to_string(), proc.start_time()))
+    }
+
+    fn send(&mut self, pid: Pid, signal: Signal) -> bool {
+        self.0
+            .process(pid)
+            .and_then(|p| p.kill_with(signal))
+            .unwrap_or(false)
+    }
+}
+
+/// SIGTERMs the builder now and SIGKILLs whatever is left after
+/// `CANCEL_GRACE_PERIOD`. Returns how many processes got the SIGTERM
+pub fn cancel(plan: CancelPlan) -> usize {
+    let signaled = signal_plan(&plan, Signal::Term, &mut SysinfoSignals::new());
+    let _ = thread::Builder::new()
+        .name("cancel-build".to_string())
+        .spawn(move || {
+            thread::sleep(CANCEL_GRACE_PERIOD);
+            signal_plan(&plan, Signal::Kill, &mut SysinfoSignals::new());
+        });
+    signaled
+}
+
+/// `plan_cancel` against the real nix users
+pub fn plan_cancel_builder(
+    builder: &str,
+    procs: &BTreeSet<ProcMetadata>,
+) -> Result<CancelPlan, CancelError> {
+    plan_cancel(builder, procs, &NIX_USERS)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// pretends to be the process table, and records signals instead of
+    /// sending them
+    #[derive(Default)]
+    struct FakeSignals {
+        owners: HashMap<Pid, (String, u64)>,
+        sent: Vec<(Pid, Signal)>,
+    }
+
+    impl SignalSender for FakeSignals {
+        fn identity(&mut self, pid: Pid) -> Option<(String, u64)> {
+            self.owners.get(&pid).cloned()
+        }
+
+        fn send(&mut self, pid: Pid, signal: Signal) -> bool {
+            self.sent.push((pid, signal));
+            true
+        }
+    }
+
+    fn nix_users() -> HashSet<String> {
+        ["nixbld1", "nixbld2"].map(String::from).into()
+    }
+
+    #[test]
+    pub fn test_plan_cancel_validates() {
+        let procs: BTreeSet<_> = [
+            ProcMetadata::fake(10, "nixbld1").with_start_time(1000),
+            ProcMetadata::fake(11, "nixbld1").with_start_time(1001),
+        ]
+        .into();
+        assert_eq!(
+            plan_cancel("nixbld1", &procs, &nix_users()),
+            Ok(CancelPlan {
+                builder: "nixbld1".to_string(),
+                pids: vec![(Pid::from(10), 1000), (Pid::from(11), 1001)],
+            })
+        );
+        assert_eq!(
+            plan_cancel("root", &procs, &nix_users()),
+            Err(CancelError::NotABuilder("root".to_string()))
+        );
+        assert_eq!(
+            plan_cancel("nixbld2", &BTreeSet::new(), &nix_users()),
+            Err(CancelError::Idle("nixbld2".to_string()))
+        );
+        let mixed: BTreeSet<_> = [
+            ProcMetadata::fake(10, "nixbld1"),
+            ProcMetadata::fake(12, "alice"),
+        ]
+        .into();
+        assert_eq!(
+            plan_cancel("nixbld1", &mixed, &nix_users()),
+            Err(CancelError::ForeignProcess {
+                pid: Pid::from(12),
+                owner: "alice".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    pub fn test_signal_plan_rechecks_owners() {
+        let plan = CancelPlan {
+            builder: "nixbld1".to_string(),
+            pids: [10, 11, 12, 13].map(|pid| (Pid::from(pid), 1000)).into(),
+        };
+        let mut signals = FakeSignals {
+            owners: [
+                (Pid::from(10), ("nixbld1".to_string(), 1000)),
+                // exited, and the pid was reused by someone else
+                (Pid::from(11), ("alice".to_string(), 1003)),
+                // exited, and the builder's next build got the pid
+                (Pid::from(13), ("nixbld1".to_string(), 1005)),
+                // 12 exited
+            ]
+            .into(),
+            ..Default::default()
+        };
+        assert_eq!(signal_plan(&plan, Signal::Term, &mut signals), 1);
+        assert_eq!(signals.sent, vec![(Pid::from(10), Signal::Term)]);
+    }
+}
@@ -0,0 +1,754 @@
+// `nix-btm --version --verbose`: everything we'd otherwise have to ask for in
+// a bug report, plus a quick check of what nix-btm needs at runtime
+use std::{
+    env,
+    fmt::Write,
+    fs::OpenOptions,
+    io,
+    net::{TcpStream, ToSocketAddrs},
+    os::unix::net::UnixStream,
+    path::Path,
+    process::Command,
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    get_stats::get_nix_users,
+    nix_cli::{nix, nix_with_timeout, retry_counts, NixError},
+};
+
+pub const DAEMON_SOCKET: &str = "/nix/var/nix/daemon-socket/socket";
+/// how often the TUI reruns `local_probes` for its status indicator
+pub const HEALTH_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// for all the substituters together, DNS included, so an air-gapped
+/// machine doesn't hang the report
+const SUBSTITUTER_TIMEOUT: Duration = Duration::from_secs(3);
+/// for each `nix store ping`
+const STORE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProbeStatus {
+    Pass,
+    Warn,
+    /// nix-btm can't do its job without this
+    Fail,
+}
+
+impl ProbeStatus {
+    fn label(self) -> &'static str {
+        match self {
+            ProbeStatus::Pass => "pass",
+            ProbeStatus::Warn => "warn",
+            ProbeStatus::Fail => "fail",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Probe {
+    pub name: &'static str,
+    pub status: ProbeStatus,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    pub rustc: &'static str,
+    pub target: &'static str,
+    pub features: &'static str,
+}
+
+pub const BUILD_INFO: BuildInfo = BuildInfo {
+    version: env!("CARGO_PKG_VERSION"),
+    git_commit: env!("NIX_BTM_GIT_COMMIT"),
+    rustc: env!("NIX_BTM_RUSTC_VERSION"),
+    target: env!("NIX_BTM_TARGET"),
+    features: env!("NIX_BTM_FEATURES"),
+};
+
+impl BuildInfo {
+    fn features(&self) -> Vec<&'static str> {
+        self.features.split(',').filter(|f| !f.is_empty()).collect()
+    }
+}
+
+/// runs `program args`, reporting the first line of its output. Never panics:
+/// a missing or failing program is reported with `missing_status`
+pub fn probe_command(
+    name: &'static str,
+    program: &str,
+    args: &[&str],
+    missing_status: ProbeStatus,
+) -> Probe {
+    let (status, detail) = match Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => (
+            ProbeStatus::Pass,
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        Ok(output) => (
+            missing_status,
+            format!("{program} exited with {}", output.status),
+        ),
+        Err(e) => (missing_status, format!("could not run {program}: {e}")),
+    };
+    Probe {
+        name,
+        status,
+        detail,
+    }
+}
+
+fn probe_supported_system() -> Probe {
+    Probe {
+        name: "supported os",
+        status: if sysinfo::IS_SUPPORTED_SYSTEM {
+            ProbeStatus::Pass
+        } else {
+            ProbeStatus::Fail
+        },
+        detail: std::env::consts::OS.to_string(),
+    }
+}
+
+fn probe_nix_users() -> Probe {
+    let num_users =
+        get_nix_users(&sysinfo::Users::new_with_refreshed_list()).len();
+    Probe {
+        name: "nixbld users",
+        status: if num_users == 0 {
+            ProbeStatus::Fail
+        } else {
+            ProbeStatus::Pass
+        },
+        detail: format!("{num_users} found"),
+    }
+}
+
+fn probe_tty() -> Probe {
+    let (status, detail) =
+        match OpenOptions::new().read(true).write(true).open("/dev/tty") {
+            Ok(_) => (ProbeStatus::Pass, "/dev/tty is accessible".to_string()),
+            Err(e) => (ProbeStatus::Warn, format!("/dev/tty: {e}")),
+        };
+    Probe {
+        name: "tty",
+        status,
+        detail,
+    }
+}
+
+/// "nothing is happening" is often the daemon being down rather than an
+/// idle machine. Single user installs have no daemon, hence only a warning
+pub fn probe_daemon_socket(nix_remote: Option<&str>, socket: &Path) -> Probe {
+    let (status, detail) = match nix_remote {
+        Some(remote) if !remote.is_empty() && remote != "daemon" => (
+            ProbeStatus::Pass,
+            format!("NIX_REMOTE={remote}, not using the local daemon"),
+        ),
+        _ => match UnixStream::connect(socket) {
+            Ok(_) => (
+                ProbeStatus::Pass,
+                format!("{} accepts connections", socket.display()),
+            ),
+            Err(e) => (ProbeStatus::Warn, format!("{}: {e}", socket.display())),
+        },
+    };
+    Probe {
+        name: "nix daemon",
+        status,
+        detail,
+    }
+}
+
+/// a single try with a deadline, never answered from the failure cache:
+/// retrying only delays the report of exactly what this probe is for, a
+/// hung daemon hangs `nix store ping` too, and the health indicator has to
+/// notice the daemon coming back. Nix before 2.19 has no --json here
+fn probe_store_ping() -> Probe {
+    let ping = nix_with_timeout(&["store", "ping", "--json"], STORE_TIMEOUT)
+        .or_else(|e| match e {
+            // the plain ping would hang just the same
+            NixError::TimedOut(_) => Err(e),
+            _ => nix_with_timeout(&["store", "ping"], STORE_TIMEOUT),
+        });
+    let (status, detail) = match ping {
+        Ok(output) => (
+            ProbeStatus::Pass,
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .find(|line| !line.trim().is_empty())
+                .unwrap_or_default()
+                .to_string(),
+        ),
+        Err(e) => (ProbeStatus::Warn, e.to_string()),
+    };
+    Probe {
+        name: "nix store",
+        status,
+        detail,
+    }
+}
+
+/// the `substituters = ...` line printed by `nix config show`
+pub fn parse_substituters(config: &str) -> Vec<String> {
+    config
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .find(|(key, _)| key.trim() == "substituters")
+        .map(|(_, urls)| urls.split_whitespace().map(String::from).collect())
+        .unwrap_or_default()
+}
+
+/// host and port of an http(s) substituter
+pub fn substituter_endpoint(url: &str) -> Option<(String, u16)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let default_port = match scheme {
+        "http" => 80,
+        "https" => 443,
+        _ => return None,
+    };
+    let authority = rest.split('/').next()?;
+    let authority = authority.rsplit_once('@').map_or(authority, |(_, a)| a);
+    let v6 = authority.strip_prefix('[').and_then(|a| a.split_once(']'));
+    let (host, port) = match v6 {
+        // [v6 address]:port
+        Some((host, rest)) => (host, rest.strip_prefix(':')),
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    if host.is_empty() {
+        return None;
+    }
+    let port = match port {
+        Some(port) => port.parse().ok()?,
+        None => default_port,
+    };
+    Some((host.to_string(), port))
+}
+
+/// resolves and connects to `host:port`, returning how long it took. The
+/// lookup itself can't be given a timeout, see `probe_substituters`
+pub fn tcp_connect(host: &str, port: u16) -> io::Result<Duration> {
+    let start = Instant::now();
+    let addr = (host, port).to_socket_addrs()?.next().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no addresses")
+    })?;
+    TcpStream::connect_timeout(&addr, SUBSTITUTER_TIMEOUT)?;
+    Ok(start.elapsed())
+}
+
+/// one probe per substituter. Only checks that something answers on the
+/// port: enough to tell "offline" from "slow". They're all probed at once,
+/// and whatever hasn't answered within `timeout` is reported as such and
+/// left to finish in the background
+pub fn probe_substituters(
+    urls: &[String],
+    connect: impl Fn(&str, u16) -> io::Result<Duration> + Send + Sync + 'static,
+    timeout: Duration,
+) -> Vec<Probe> {
+    let deadline = Instant::now() + timeout;
+    let connect = Arc::new(connect);
+    let pending: Vec<_> = urls
+        .iter()
+        .map(|url| {
+            let answer = substituter_endpoint(url).map(|(host, port)| {
+                let (tx, rx) = mpsc::channel();
+                let connect = Arc::clone(&connect);
+                thread::spawn(move || {
+                    let _ = tx.send(connect(&host, port));
+                });
+                rx
+            });
+            (url, answer)
+        })
+        .collect();
+    pending
+        .into_iter()
+        .map(|(url, answer)| {
+            let (status, detail) = match answer {
+                None => {
+                    (ProbeStatus::Pass, format!("{url}: not a network store"))
+                }
+                Some(answer) => match answer.recv_timeout(
+                    deadline.saturating_duration_since(Instant::now()),
+                ) {
+                    Ok(Ok(latency)) => (
+                        ProbeStatus::Pass,
+                        format!(
+                            "{url}: reachable in {}ms",
+                            latency.as_millis()
+                        ),
+                    ),
+                    Ok(Err(e)) => (ProbeStatus::Warn, format!("{url}: {e}")),
+                    Err(_) => (
+                        ProbeStatus::Warn,
+                        format!(
+                            "{url}: no answer within {}ms",
+                            timeout.as_millis()
+                        ),
+                    ),
+                },
+            };
+            Probe {
+                name: "substituter",
+                status,
+                detail,
+            }
+        })
+        .collect()
+}
+
+/// transient nix failures we retried while gathering the report. Retries
+/// mean a busy or struggling store, even if everything passed in the end
+pub fn probe_nix_retries(counts: &[(String, u64)]) -> Probe {
+    let (status, detail) = if counts.is_empty() {
+        (ProbeStatus::Pass, "none".to_string())
+    } else {
+        let counts: Vec<_> = counts
+            .iter()
+            .map(|(subcommand, count)| format!("{subcommand} {count}"))
+            .collect();
+        (ProbeStatus::Warn, counts.join(", "))
+    };
+    Probe {
+        name: "nix retries",
+        status,
+        detail,
+    }
+}
+
+/// the probes that stay on this machine, cheap enough to rerun while the TUI
+/// is up
+pub fn local_probes() -> Vec<Probe> {
+    vec![
+        probe_daemon_socket(
+            env::var("NIX_REMOTE").ok().as_deref(),
+            Path::new(DAEMON_SOCKET),
+        ),
+        probe_store_ping(),
+    ]
+}
+
+/// the worst of `probes`, None if there aren't any
+pub fn overall_status(probes: &[Probe]) -> Option<ProbeStatus> {
+    probes.iter().map(|probe| probe.status).max()
+}
+
+/// reruns `local_probes` on a background thread: at startup, every
+/// `HEALTH_INTERVAL` and whenever asked to. A hung daemon can make
+/// `nix store ping` hang too, which must not hold up drawing or sampling
+#[derive(Debug, Default, Clone)]
+pub struct HealthMonitor {
+    latest: Arc<Mutex<Vec<Probe>>>,
+    probe_requested: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl HealthMonitor {
+    pub fn spawn(interval: Duration) -> Self {
+        let monitor = HealthMonitor::default();
+        let handle = monitor.clone();
+        thread::Builder::new()
+            .name("health-probes".to_string())
+            .spawn(move || loop {
+                handle.publish(local_probes());
+                handle.wait(interval);
+            })
+            .expect("Failed to spawn health probe thread");
+        monitor
+    }
+
+    fn wait(&self, interval: Duration) {
+        let (lock, requested) = &*self.probe_requested;
+        let guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut guard, _) = requested
+            .wait_timeout_while(guard, interval, |requested| !*requested)
+            .unwrap_or_else(|e| e.into_inner());
+        *guard = false;
+    }
+
+    /// rerun the probes now, regardless of the interval
+    pub fn request_probe(&self) {
+        let (lock, requested) = &*self.probe_requested;
+        *lock.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        requested.notify_all();
+    }
+
+    pub fn publish(&self, probes: Vec<Probe>) {
+        *self.latest.lock().unwrap_or_else(|e| e.into_inner()) = probes;
+    }
+
+    /// empty until the first round finishes, and forever with --no-probes
+    pub fn latest(&self) -> Vec<Probe> {
+        self.latest
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
+
+/// everything `--version --verbose` reports. `network` is false with
+/// --no-probes, for air-gapped machines where every substituter would just
+/// time out
+pub fn run_probes(network: bool) -> Vec<Probe> {
+    let tracer = if cfg!(target_os = "macos") {
+        "dtruss"
+    } else {
+        "strace"
+    };
+    let mut probes = vec![
+        probe_supported_system(),
+        probe_nix_users(),
+        probe_command("nix", "nix", &["--version"], ProbeStatus::Warn),
+    ];
+    probes.extend(local_probes());
+    probes.push(probe_command("tracer", tracer, &["-V"], ProbeStatus::Warn));
+    probes.push(probe_tty());
+    if network {
+        let substituters = nix(&["config", "show"])
+            .map(|output| {
+                parse_substituters(&String::from_utf8_lossy(&output.stdout))
+            })
+            .unwrap_or_default();
+        probes.extend(probe_substituters(
+            &substituters,
+            tcp_connect,
+            SUBSTITUTER_TIMEOUT,
+        ));
+    }
+    probes.push(probe_nix_retries(&retry_counts()));
+    probes
+}
+
+pub fn render_text(info: &BuildInfo, probes: &[Probe]) -> String {
+    let mut out = format!("nix-btm {} ({})\n", info.version, info.git_commit);
+    let features = info.features();
+    let _ = writeln!(out, "{}, target {}", info.rustc, info.target);
+    let _ = writeln!(
+        out,
+        "features: {}",
+        if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(", ")
+        }
+    );
+    for probe in probes {
+        let _ = writeln!(
+            out,
+            "[{}] {}: {}",
+            probe.status.label(),
+            probe.name,
+            probe.detail
+        );
+    }
+    out
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// same information as `render_text`, for attaching to bug reports
+pub fn render_json(info: &BuildInfo, probes: &[Probe]) -> String {
+    let features: Vec<String> =
+        info.features().into_iter().map(json_string).collect();
+    let probes: Vec<String> = probes
+        .iter()
+        .map(|probe| {
+            format!(
+                "{{\"name\":{},\"status\":{},\"detail\":{}}}",
+                json_string(probe.name),
+                json_string(probe.status.label()),
+                json_string(&probe.detail)
+            )
+        })
+        .collect();
+    format!(
+        "{{\"version\":{},\"git_commit\":{},\"rustc\":{},\"target\":{},\
+         \"features\":[{}],\"probes\":[{}]}}",
+        json_string(info.version),
+        json_string(info.git_commit),
+        json_string(info.rustc),
+        json_string(info.target),
+        features.join(","),
+        probes.join(",")
+    )
+}
+
+/// prints the version (and, if `verbose`, the diagnostics) and returns the
+/// exit code: nonzero if any hard requirement failed
+pub fn print_version(verbose: bool, json: bool, network: bool) -> i32 {
+    if !verbose && !json {
+        println!("nix-btm {}", BUILD_INFO.version);
+        return 0;
+    }
+    let probes = run_probes(network);
+    if json {
+        println!("{}", render_json(&BUILD_INFO, &probes));
+    } else {
+        print!("{}", render_text(&BUILD_INFO, &probes));
+    }
+    if probes.iter().any(|p| p.status == ProbeStatus::Fail) {
+        1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: BuildInfo = BuildInfo {
+        version: "0.2.0",
+        git_commit: "abc1234",
+        rustc: "rustc 1.80.0",
+        target: "x86_64-unknown-linux-gnu",
+        features: "",
+    };
+
+    fn probes() -> Vec<Probe> {
+        vec![
+            Probe {
+                name: "nix",
+                status: ProbeStatus::Pass,
+                detail: "nix (Nix) 2.24.0".to_string(),
+            },
+            Probe {
+                name: "tty",
+                status: ProbeStatus::Warn,
+                detail: "/dev/tty: \"No such device\"\n".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    pub fn test_render_json() {
+        assert_eq!(
+            render_json(&INFO, &probes()),
+            "{\"version\":\"0.2.0\",\"git_commit\":\"abc1234\",\
+             \"rustc\":\"rustc 1.80.0\",\
+             \"target\":\"x86_64-unknown-linux-gnu\",\"features\":[],\
+             \"probes\":[\
+             {\"name\":\"nix\",\"status\":\"pass\",\
+             \"detail\":\"nix (Nix) 2.24.0\"},\
+             {\"name\":\"tty\",\"status\":\"warn\",\
+             \"detail\":\"/dev/tty: \\\"No such device\\\"\\n\"}]}"
+        );
+        let info = BuildInfo {
+            features: "a,b",
+            ..INFO
+        };
+        assert!(render_json(&info, &[]).contains("\"features\":[\"a\",\"b\"]"));
+    }
+
+    #[test]
+    pub fn test_probe_nix_retries() {
+        assert_eq!(probe_nix_retries(&[]).status, ProbeStatus::Pass);
+        let probe = probe_nix_retries(&[
+            ("config".to_string(), 2),
+            ("store".to_string(), 1),
+        ]);
+        assert_eq!(probe.status, ProbeStatus::Warn);
+        assert_eq!(probe.detail, "config 2, store 1");
+    }
+
+    #[test]
+    pub fn test_overall_status() {
+        assert_eq!(overall_status(&[]), None);
+        let mut probes = probes();
+        assert_eq!(overall_status(&probes), Some(ProbeStatus::Warn));
+        probes[0].status = ProbeStatus::Fail;
+        assert_eq!(overall_status(&probes), Some(ProbeStatus::Fail));
+        probes[0].status = ProbeStatus::Pass;
+        probes[1].status = ProbeStatus::Pass;
+        assert_eq!(overall_status(&probes), Some(ProbeStatus::Pass));
+
+        // nothing until something is published
+        let monitor = HealthMonitor::default();
+        assert!(monitor.latest().is_empty());
+        monitor.publish(probes.clone());
+        assert_eq!(monitor.latest(), probes);
+    }
+
+    #[test]
+    pub fn test_render_text() {
+        let text = render_text(&INFO, &probes());
+        assert!(text.starts_with("nix-btm 0.2.0 (abc1234)\n"));
+        assert!(text.contains("features: none\n"));
+        assert!(text.contains("[pass] nix: nix (Nix) 2.24.0\n"));
+        assert!(text.contains("[warn] tty: "));
+    }
+
+    #[test]
+    pub fn test_missing_program_is_reported() {
+        let probe = probe_command(
+            "missing",
+            "nix-btm-definitely-not-a-real-program",
+            &[],
+            ProbeStatus::Fail,
+        );
+        assert_eq!(probe.status, ProbeStatus::Fail);
+        assert!(probe.detail.starts_with("could not run"));
+
+        let probe = probe_command("false", "false", &[], ProbeStatus::Warn);
+        assert_eq!(probe.status, ProbeStatus::Warn);
+    }
+
+    #[test]
+    pub fn test_probe_daemon_socket() {
+        use std::os::unix::net::UnixListener;
+
+        let socket = env::temp_dir()
+            .join(format!("nix-btm-daemon-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        assert_eq!(
+            probe_daemon_socket(None, &socket).status,
+            ProbeStatus::Warn
+        );
+
+        let listener = UnixListener::bind(&socket).unwrap();
+        assert_eq!(
+            probe_daemon_socket(None, &socket).status,
+            ProbeStatus::Pass
+        );
+        assert_eq!(
+            probe_daemon_socket(Some("daemon"), &socket).status,
+            ProbeStatus::Pass
+        );
+        drop(listener);
+        let _ = std::fs::remove_file(&socket);
+
+        let probe = probe_daemon_socket(Some("local"), &socket);
+        assert_eq!(probe.status, ProbeStatus::Pass);
+        assert!(probe.detail.contains("NIX_REMOTE=local"));
+    }
+
+    #[test]
+    pub fn test_substituter_endpoints() {
+        assert_eq!(
+            parse_substituters(
+                "cores = 0\nsubstituters = https://cache.nixos.org/ \
+                 http://10.0.0.2:5000 file:///srv/cache\n"
+            ),
+            vec![
+                "https://cache.nixos.org/",
+                "http://10.0.0.2:5000",
+                "file:///srv/cache"
+            ]
+        );
+        assert_eq!(parse_substituters("cores = 0"), Vec::<String>::new());
+        for (url, expected) in [
+            ("https://cache.nixos.org/", Some(("cache.nixos.org", 443))),
+            ("http://10.0.0.2:5000", Some(("10.0.0.2", 5000))),
+            (
+                "https://user:pw@cache.example/x",
+                Some(("cache.example", 443)),
+            ),
+            ("http://[::1]", Some(("::1", 80))),
+            ("http://[::1]:8080/", Some(("::1", 8080))),
+            ("http://cache:notaport", None),
+            ("file:///srv/cache", None),
+            ("s3://bucket", None),
+            ("https://", None),
+        ] {
+            assert_eq!(
+                substituter_endpoint(url),
+                expected.map(|(host, port)| (host.to_string(), port)),
+                "{url}"
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_probe_substituters() {
+        let urls: Vec<String> = [
+            "https://cache.nixos.org/",
+            "https://down.example",
+            "file:///srv/cache",
+        ]
+        .map(String::from)
+        .into();
+        let probes = probe_substituters(
+            &urls,
+            |host, port| {
+                assert_eq!(port, 443);
+                if host == "cache.nixos.org" {
+                    Ok(Duration::from_millis(23))
+                } else {
+                    Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+                }
+            },
+            Duration::from_secs(60),
+        );
+        let statuses: Vec<_> = probes.iter().map(|p| p.status).collect();
+        assert_eq!(
+            statuses,
+            vec![ProbeStatus::Pass, ProbeStatus::Warn, ProbeStatus::Pass]
+        );
+        assert_eq!(
+            probes[0].detail,
+            "https://cache.nixos.org/: reachable in 23ms"
+        );
+        assert_eq!(probes[1].detail, "https://down.example: timed out");
+        assert!(
+            probe_substituters(&[], |_, _| unreachable!(), Duration::ZERO)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    pub fn test_hung_substituter_is_abandoned() {
+        let urls: Vec<String> =
+            ["https://hung.example", "https://cache.nixos.org"]
+                .map(String::from)
+                .into();
+        // a lookup that never returns. Reaching the asserts at all shows
+        // the probe didn't wait for it
+        let probes = probe_substituters(
+            &urls,
+            |host, _| {
+                if host == "hung.example" {
+                    loop {
+                        thread::park();
+                    }
+                }
+                Ok(Duration::from_millis(5))
+            },
+            Duration::from_millis(200),
+        );
+        assert_eq!(probes[0].status, ProbeStatus::Warn);
+        assert_eq!(
+            probes[0].detail,
+            "https://hung.example: no answer within 200ms"
+        );
+        assert_eq!(probes[1].status, ProbeStatus::Pass);
+    }
+}
@@ -1,130 +1,859 @@
-use std::{io, ops::Deref, time::Duration};
+use std::{
+    collections::BTreeSet,
+    env,
+    io::{self, Stdout, Write},
+    ops::Deref,
+    panic,
+    time::{Duration, Instant},
+};
 
-use crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use crossterm::{
+    event::{
+        self, DisableFocusChange, DisableMouseCapture, EnableFocusChange,
+        Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+    },
+    execute,
+    terminal::{
+        disable_raw_mode, enable_raw_mode, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
+};
+use ratatui::{
+    backend::{Backend, CrosstermBackend},
+    Terminal,
+};
+use sysinfo::Pid;
 
 use crate::{
+    cancel::{cancel, plan_cancel_builder, CancelPlan},
+    export::{export, osc52_sequence, ExportTarget},
     get_stats::{NIX_USERS, SORTED_NIX_USERS},
+    proc_table::ProcColumn,
+    session::{set_title_sequence, RESTORE_TITLE_SEQUENCE},
     ui::ui,
-    App, Pane, Terminal,
+    App, Pane, SelectedTab,
 };
 
-pub fn event_loop(terminal: &mut Terminal, mut app: App) -> io::Result<()> {
+/// draw at most this often, regardless of how fast input arrives
+pub const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+/// frame interval while unfocused or in low power mode
+pub const IDLE_FRAME_INTERVAL: Duration = Duration::from_secs(2);
+
+/// bounds for user supplied intervals. Anything faster burns cpu for
+/// nothing, anything slower looks frozen
+pub const MIN_INTERVAL: Duration = Duration::from_millis(10);
+pub const MAX_INTERVAL: Duration = Duration::from_secs(60);
+
+pub fn frame_interval(app: &App) -> Duration {
+    if app.is_idle() {
+        IDLE_FRAME_INTERVAL
+    } else {
+        app.frame_interval.unwrap_or(FRAME_INTERVAL)
+    }
+}
+
+/// parses "250ms", "5s" or a bare number of seconds, and checks it's within
+/// `min..=max`
+pub fn parse_interval(
+    s: &str,
+    min: Duration,
+    max: Duration,
+) -> Result<Duration, String> {
+    let parsed = if let Some(ms) = s.strip_suffix("ms") {
+        ms.parse().map(Duration::from_millis).ok()
+    } else {
+        s.strip_suffix('s')
+            .unwrap_or(s)
+            .parse::<f64>()
+            .ok()
+            .filter(|secs| secs.is_finite() && *secs >= 0.0)
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+    };
+    let interval = parsed.ok_or_else(|| {
+        format!("{s:?} is not an interval, try e.g. 500ms or 5s")
+    })?;
+    if interval < min || interval > max {
+        return Err(format!(
+            "{s} is out of range, must be between {}ms and {}s",
+            min.as_millis(),
+            max.as_secs()
+        ));
+    }
+    Ok(interval)
+}
+
+/// somewhere to pull terminal events from. Exists so tests can script key
+/// presses instead of needing a real tty
+pub trait InputSource {
+    /// wait up to `timeout` for the next event
+    fn next_event(&mut self, timeout: Duration) -> io::Result<Option<Event>>;
+}
+
+pub struct CrosstermInput;
+
+impl InputSource for CrosstermInput {
+    fn next_event(&mut self, timeout: Duration) -> io::Result<Option<Event>> {
+        if event::poll(timeout)? {
+            event::read().map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// undoes everything `TerminalGuard::new` did to the terminal. Safe to call
+/// more than once, and from the panic hook
+pub fn restore_terminal(out: &mut impl Write, titled: bool) -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(
+        out,
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        DisableFocusChange,
+        crossterm::cursor::Show
+    )?;
+    if titled {
+        write!(out, "{RESTORE_TITLE_SEQUENCE}")?;
+    }
+    out.flush()
+}
+
+/// owns raw mode and the alternate screen for as long as it lives. The
+/// terminal is restored when it's dropped, and by the panic hook it installs
+/// (with panic = "abort" nothing is dropped on a panic)
+pub struct TerminalGuard {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    titled: bool,
+}
+
+impl TerminalGuard {
+    pub fn new(session_name: Option<&str>) -> io::Result<Self> {
+        let titled = session_name.is_some();
+        let panic_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |panic| {
+            let _ = restore_terminal(&mut io::stderr(), titled);
+            panic_hook(panic);
+        }));
+
+        enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        // terminals that can't report focus just never send the events
+        execute!(stdout, EnterAlternateScreen, EnableFocusChange)?;
+        if let Some(session_name) = session_name {
+            write!(stdout, "{}", set_title_sequence(session_name))?;
+            stdout.flush()?;
+        }
+        // from here on, an error still restores the terminal via drop
+        let mut guard = TerminalGuard {
+            terminal: Terminal::new(CrosstermBackend::new(stdout))?,
+            titled,
+        };
+        guard.terminal.hide_cursor()?;
+        Ok(guard)
+    }
+
+    pub fn terminal(&mut self) -> &mut Terminal<CrosstermBackend<Stdout>> {
+        &mut self.terminal
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = restore_terminal(self.terminal.backend_mut(), self.titled);
+    }
+}
+
+/// side effects requested by `update`. The loop executes these so that the
+/// state transitions themselves stay testable
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Effect {
+    Quit,
+    /// draw now instead of waiting for the next frame
+    Redraw,
+    /// signal a builder's processes, after the user confirmed
+    Cancel(CancelPlan),
+    /// write the builder view to the clipboard or a file
+    Export(ExportTarget),
+    /// put this on the clipboard
+    Copy(String),
+}
+
+pub fn event_loop<B: Backend>(
+    terminal: &mut Terminal<B>,
+    mut app: App,
+    input: &mut impl InputSource,
+) -> io::Result<()> {
     loop {
         terminal.draw(|f| ui(f, &mut app))?;
+        let frame_start = Instant::now();
 
-        // TODO fix scrolling to only scroll by root node
-        if event::poll(Duration::from_millis(32))? {
-            if let Event::Key(key) = event::read()? {
-                if key.kind == KeyEventKind::Press {
-                    match key.code {
-                        KeyCode::Char('g') => {
-                            app.builder_view
-                                .state
-                                .select(vec![SORTED_NIX_USERS[0].clone()]);
-                        }
-                        KeyCode::Char('G') => {
-                            app.builder_view.state.select(vec![
-                                SORTED_NIX_USERS[SORTED_NIX_USERS.len() - 1]
-                                    .clone(),
-                            ]);
-                        }
-                        KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
-                        KeyCode::Tab => {
-                            let num_open =
-                                app.builder_view.state.opened().len();
-                            if num_open == NIX_USERS.len() {
-                                app.builder_view.state.close_all();
-                            } else {
-                                for user in Deref::deref(&NIX_USERS) {
-                                    app.builder_view
-                                        .state
-                                        .open(vec![user.to_string()]);
-                                }
-                            }
-                        }
-                        KeyCode::Char('j') | KeyCode::Down => {
-                            if let Some(selected) =
-                                app.builder_view.state.selected().first()
-                            {
-                                let idx = SORTED_NIX_USERS
-                                    .iter()
-                                    .position(|x| x == selected)
-                                    .unwrap();
-                                let new_idx =
-                                    (idx + 1) % SORTED_NIX_USERS.len();
-                                app.builder_view.state.select(vec![
-                                    SORTED_NIX_USERS[new_idx].clone(),
-                                ]);
-                            } else {
-                                app.builder_view
-                                    .state
-                                    .select(vec![SORTED_NIX_USERS[0].clone()]);
-                            }
-                        }
-                        KeyCode::Char('k') | KeyCode::Up => {
-                            if let Some(selected) =
-                                app.builder_view.state.selected().first()
-                            {
-                                let idx = SORTED_NIX_USERS
-                                    .iter()
-                                    .position(|x| x == selected)
-                                    .unwrap();
-                                let new_idx =
-                                    (idx - 1) % SORTED_NIX_USERS.len();
-                                app.builder_view.state.select(vec![
-                                    SORTED_NIX_USERS[new_idx].clone(),
-                                ]);
-                            } else {
-                                app.builder_view
-                                    .state
-                                    .select(vec![SORTED_NIX_USERS[0].clone()]);
-                            }
-                        }
-                        KeyCode::Char('h') => {
-                            app.builder_view.go_left();
-                        }
-                        KeyCode::Char('l') => {
-                            app.builder_view.go_right();
-                        }
-                        KeyCode::Char('<') | KeyCode::Left => {
-                            if app.builder_view.selected_pane == Pane::Right {
-                                app.builder_view.horizontal_scroll = app
-                                    .builder_view
-                                    .horizontal_scroll
-                                    .saturating_sub(1);
-                            }
-                        }
-                        KeyCode::Char('>') | KeyCode::Right => {
-                            if app.builder_view.selected_pane == Pane::Right {
-                                app.builder_view.horizontal_scroll += 1;
-                            }
-                        }
-                        KeyCode::Enter => {
-                            // HACK the api has a cleaner way
-                            if !app.builder_view.state.key_right() {
-                                app.builder_view.state.key_left();
-                            }
-                        }
-                        KeyCode::Char('M') => match app.tab_selected {
-                            crate::SelectedTab::BuilderView => {
-                                app.builder_view.man_toggle =
-                                    !app.builder_view.man_toggle;
-                            }
-                            crate::SelectedTab::BirdsEyeView => {
-                                app.birds_eye_view.man_toggle =
-                                    !app.birds_eye_view.man_toggle;
-                            }
-                        },
-                        KeyCode::Char('n') => {
-                            app.tab_selected = app.tab_selected.next();
-                        }
-                        KeyCode::Char('p') => {
-                            app.tab_selected = app.tab_selected.previous();
-                        }
-                        _ => {}
+        // handle everything that arrives before the next frame is due
+        loop {
+            let remaining =
+                frame_interval(&app).saturating_sub(frame_start.elapsed());
+            if remaining.is_zero() {
+                break;
+            }
+            let Some(event) = input.next_event(remaining)? else {
+                break;
+            };
+            // show the result of a key press right away, rather than on the
+            // next frame which can be seconds out when idle
+            let mut redraw = matches!(event, Event::Key(_));
+            for effect in update(&mut app, event) {
+                match effect {
+                    Effect::Quit => return Ok(()),
+                    Effect::Redraw => redraw = true,
+                    Effect::Cancel(plan) => {
+                        let builder = plan.builder.clone();
+                        let signaled = cancel(plan);
+                        app.builder_view.status_message = Some(format!(
+                            "sent SIGTERM to {signaled} processes of \
+                             {builder}"
+                        ));
+                        redraw = true;
+                    }
+                    Effect::Copy(text) => {
+                        let mut stdout = io::stdout();
+                        let copied = stdout
+                            .write_all(osc52_sequence(&text).as_bytes())
+                            .and_then(|()| stdout.flush());
+                        app.builder_view.status_message = Some(match copied {
+                            Ok(()) => "copied!".to_string(),
+                            Err(e) => format!("could not copy: {e}"),
+                        });
+                        redraw = true;
                     }
+                    Effect::Export(target) => {
+                        let snapshot = app.builder_view.proc_poller.snapshot();
+                        let dir = env::current_dir().unwrap_or_default();
+                        let status = export(
+                            &snapshot.user_map,
+                            target,
+                            &mut io::stdout(),
+                            &dir,
+                        );
+                        app.builder_view.status_message = Some(status);
+                        redraw = true;
+                    }
+                }
+            }
+            if redraw {
+                break;
+            }
+        }
+    }
+}
+
+pub fn update(app: &mut App, event: Event) -> Vec<Effect> {
+    match event {
+        Event::Key(key) if key.kind == KeyEventKind::Press => {
+            handle_key(app, key)
+        }
+        Event::FocusLost => {
+            app.unfocused = true;
+            app.builder_view.proc_poller.set_paused(true);
+            vec![]
+        }
+        Event::FocusGained => {
+            app.unfocused = false;
+            app.builder_view.proc_poller.set_paused(false);
+            vec![Effect::Redraw]
+        }
+        // resizes are picked up by the next draw
+        _ => vec![],
+    }
+}
+
+/// moves the selection `offset` builders down, wrapping around. Starts from
+/// the top if nothing (or a builder that no longer exists) is selected
+fn select_builder(app: &mut App, offset: usize) {
+    let len = SORTED_NIX_USERS.len();
+    if len == 0 {
+        return;
+    }
+    let new_idx = app
+        .builder_view
+        .state
+        .selected()
+        .first()
+        .and_then(|selected| {
+            SORTED_NIX_USERS.iter().position(|x| x == selected)
+        })
+        .map_or(0, |idx| (idx + offset) % len);
+    app.builder_view
+        .state
+        .select(vec![SORTED_NIX_USERS[new_idx].clone()]);
+    app.builder_view.proc_table.selected = None;
+}
+
+/// pids of the selected builder's processes, in table order
+fn table_rows(app: &App) -> Vec<Pid> {
+    let snapshot = app.builder_view.proc_poller.snapshot();
+    let selected = app.builder_view.state.selected();
+    match selected.first().and_then(|b| snapshot.user_map.get(b)) {
+        Some(procs) => app
+            .builder_view
+            .proc_table
+            .rows(procs)
+            .iter()
+            .map(|p| p.id)
+            .collect(),
+        None => vec![],
+    }
+}
+
+/// full command line of the process selected in the table
+fn selected_cmd(app: &App) -> Option<String> {
+    let pid = app.builder_view.proc_table.selected?;
+    let snapshot = app.builder_view.proc_poller.snapshot();
+    let selected = app.builder_view.state.selected();
+    let procs = snapshot.user_map.get(selected.first()?)?;
+    let proc = procs.iter().find(|p| p.id == pid)?;
+    Some(proc.cmd.join(" "))
+}
+
+/// j/k/g/G and paging while the process table has focus. Returns whether
+/// the key was one of those
+fn handle_table_key(app: &mut App, code: KeyCode) -> bool {
+    use KeyCode::*;
+    if !matches!(
+        code,
+        Char('j' | 'k' | 'g' | 'G') | Down | Up | PageDown | PageUp
+    ) {
+        return false;
+    }
+    let rows = table_rows(app);
+    let table = &mut app.builder_view.proc_table;
+    let page = table.page_height.max(1) as isize;
+    match code {
+        Char('g') => table.select_first(&rows),
+        Char('G') => table.select_last(&rows),
+        Char('j') | Down => table.move_selection(&rows, 1),
+        Char('k') | Up => table.move_selection(&rows, -1),
+        PageDown => table.move_selection(&rows, page),
+        _ => table.move_selection(&rows, -page),
+    }
+    true
+}
+
+fn handle_key(app: &mut App, key: KeyEvent) -> Vec<Effect> {
+    // raw mode turns ctrl-c into a key press rather than SIGINT
+    if key.code == KeyCode::Char('c')
+        && key.modifiers.contains(KeyModifiers::CONTROL)
+    {
+        return vec![Effect::Quit];
+    }
+    // the confirmation prompt swallows the next key. It's only drawn on the
+    // builder view, so never confirm anything from elsewhere
+    if let Some(plan) = app.builder_view.pending_cancel.take() {
+        if key.code == KeyCode::Char('y') && app.builder_view_visible() {
+            return vec![Effect::Cancel(plan)];
+        }
+        app.builder_view.status_message = Some("cancel aborted".to_string());
+        return vec![];
+    }
+    if app.builder_view.selected_pane == Pane::Right
+        && handle_table_key(app, key.code)
+    {
+        return vec![];
+    }
+    // TODO fix scrolling to only scroll by root node
+    match key.code {
+        KeyCode::Char('g') => {
+            if let Some(first) = SORTED_NIX_USERS.first() {
+                app.builder_view.state.select(vec![first.clone()]);
+                app.builder_view.proc_table.selected = None;
+            }
+        }
+        KeyCode::Char('G') => {
+            if let Some(last) = SORTED_NIX_USERS.last() {
+                app.builder_view.state.select(vec![last.clone()]);
+                app.builder_view.proc_table.selected = None;
+            }
+        }
+        KeyCode::Char('q') | KeyCode::Esc => return vec![Effect::Quit],
+        KeyCode::Char('x') if app.builder_view_visible() => {
+            let snapshot = app.builder_view.proc_poller.snapshot();
+            let selected = app.builder_view.state.selected();
+            // only whole builders can be cancelled, not single processes
+            if let [builder] = selected {
+                let plan = plan_cancel_builder(
+                    builder,
+                    snapshot.user_map.get(builder).unwrap_or(&BTreeSet::new()),
+                );
+                match plan {
+                    Ok(plan) => app.builder_view.pending_cancel = Some(plan),
+                    Err(e) => {
+                        app.builder_view.status_message = Some(e.to_string())
+                    }
+                }
+            } else {
+                app.builder_view.status_message =
+                    Some("select a builder to cancel".to_string());
+            }
+        }
+        KeyCode::Char(c @ '1'..='9') => {
+            if let Some(column) = ProcColumn::from_key(c) {
+                app.builder_view.proc_table.sort.select(column);
+            }
+        }
+        KeyCode::Char('y') => match selected_cmd(app) {
+            Some(cmd) => return vec![Effect::Copy(cmd)],
+            None => {
+                app.builder_view.status_message =
+                    Some("select a process to copy its command".to_string())
+            }
+        },
+        KeyCode::Char('Y') => {
+            return vec![Effect::Export(ExportTarget::Clipboard)]
+        }
+        KeyCode::Char('W') => return vec![Effect::Export(ExportTarget::File)],
+        KeyCode::Char('r') => {
+            app.builder_view.proc_poller.request_refresh();
+            app.health.request_probe();
+            return vec![Effect::Redraw];
+        }
+        KeyCode::Tab => {
+            let num_open = app.builder_view.state.opened().len();
+            if num_open == NIX_USERS.len() {
+                app.builder_view.state.close_all();
+            } else {
+                for user in Deref::deref(&NIX_USERS) {
+                    app.builder_view.state.open(vec![user.to_string()]);
                 }
             }
         }
+        KeyCode::Char('j') | KeyCode::Down => {
+            select_builder(app, 1);
+        }
+        KeyCode::Char('k') | KeyCode::Up => {
+            select_builder(app, SORTED_NIX_USERS.len().saturating_sub(1));
+        }
+        KeyCode::Char('h') => {
+            app.builder_view.go_left();
+        }
+        KeyCode::Char('l') => {
+            app.builder_view.go_right();
+        }
+        KeyCode::Char('<') | KeyCode::Left => {
+            if app.builder_view.selected_pane == Pane::Right {
+                app.builder_view.horizontal_scroll =
+                    app.builder_view.horizontal_scroll.saturating_sub(1);
+            }
+        }
+        KeyCode::Char('>') | KeyCode::Right => {
+            if app.builder_view.selected_pane == Pane::Right {
+                app.builder_view.horizontal_scroll += 1;
+            }
+        }
+        KeyCode::Enter => {
+            // HACK the api has a cleaner way
+            if !app.builder_view.state.key_right() {
+                app.builder_view.state.key_left();
+            }
+        }
+        KeyCode::Char('M') => match app.tab_selected {
+            SelectedTab::BuilderView => {
+                app.builder_view.man_toggle = !app.builder_view.man_toggle;
+            }
+            SelectedTab::BirdsEyeView => {
+                app.birds_eye_view.man_toggle = !app.birds_eye_view.man_toggle;
+            }
+        },
+        KeyCode::Char('n') => {
+            app.tab_selected = app.tab_selected.next();
+        }
+        KeyCode::Char('p') => {
+            app.tab_selected = app.tab_selected.previous();
+        }
+        _ => {}
+    }
+    vec![]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, io, time::Duration};
+
+    use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
+    use ratatui::{backend::TestBackend, Terminal};
+
+    use super::{
+        event_loop, frame_interval, parse_interval, update, Effect,
+        InputSource, FRAME_INTERVAL, IDLE_FRAME_INTERVAL, MAX_INTERVAL,
+        MIN_INTERVAL,
+    };
+    use crate::{App, Pane, SelectedTab};
+
+    /// replays a fixed list of events, then errors out so a test that forgot
+    /// to quit doesn't spin forever
+    struct ScriptedInput(VecDeque<Event>);
+
+    impl InputSource for ScriptedInput {
+        fn next_event(
+            &mut self,
+            _timeout: Duration,
+        ) -> io::Result<Option<Event>> {
+            self.0.pop_front().map(Some).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "script ended")
+            })
+        }
+    }
+
+    fn key(code: KeyCode) -> Event {
+        Event::Key(KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    struct Expected {
+        tab: SelectedTab,
+        pane: Pane,
+        horizontal_scroll: usize,
+        builder_man: bool,
+        birds_eye_man: bool,
+        quit: bool,
+    }
+
+    const DEFAULT: Expected = Expected {
+        tab: SelectedTab::BuilderView,
+        pane: Pane::Left,
+        horizontal_scroll: 0,
+        builder_man: false,
+        birds_eye_man: false,
+        quit: false,
+    };
+
+    #[test]
+    pub fn test_key_sequences() {
+        use KeyCode::*;
+        let cases: Vec<(&str, Vec<KeyCode>, Expected)> = vec![
+            ("nothing", vec![], DEFAULT),
+            (
+                "next tab",
+                vec![Char('n')],
+                Expected {
+                    tab: SelectedTab::BirdsEyeView,
+                    ..DEFAULT
+                },
+            ),
+            ("next tab wraps", vec![Char('n'), Char('n')], DEFAULT),
+            (
+                "previous tab wraps",
+                vec![Char('p')],
+                Expected {
+                    tab: SelectedTab::BirdsEyeView,
+                    ..DEFAULT
+                },
+            ),
+            (
+                "manual is per tab",
+                vec![Char('M'), Char('n')],
+                Expected {
+                    tab: SelectedTab::BirdsEyeView,
+                    builder_man: true,
+                    ..DEFAULT
+                },
+            ),
+            ("manual toggles off", vec![Char('M'), Char('M')], DEFAULT),
+            ("scroll needs right pane", vec![Char('>')], DEFAULT),
+            (
+                "scroll right pane",
+                vec![Char('l'), Char('>'), Right, Char('>'), Left],
+                Expected {
+                    pane: Pane::Right,
+                    horizontal_scroll: 2,
+                    ..DEFAULT
+                },
+            ),
+            (
+                "scroll saturates at zero",
+                vec![Char('l'), Char('<'), Char('<'), Char('h')],
+                DEFAULT,
+            ),
+            (
+                "quit",
+                vec![Char('q')],
+                Expected {
+                    quit: true,
+                    ..DEFAULT
+                },
+            ),
+            (
+                "esc quits",
+                vec![Esc],
+                Expected {
+                    quit: true,
+                    ..DEFAULT
+                },
+            ),
+        ];
+
+        for (name, keys, expected) in cases {
+            let mut app = App::default();
+            let mut quit = false;
+            for code in keys {
+                quit |= update(&mut app, key(code)).contains(&Effect::Quit);
+            }
+            assert_eq!(app.tab_selected, expected.tab, "{name}");
+            assert_eq!(app.builder_view.selected_pane, expected.pane, "{name}");
+            assert_eq!(
+                app.builder_view.horizontal_scroll, expected.horizontal_scroll,
+                "{name}"
+            );
+            assert_eq!(
+                app.builder_view.man_toggle, expected.builder_man,
+                "{name}"
+            );
+            assert_eq!(
+                app.birds_eye_view.man_toggle, expected.birds_eye_man,
+                "{name}"
+            );
+            assert_eq!(quit, expected.quit, "{name}");
+        }
+    }
+
+    #[test]
+    pub fn test_event_loop_exits_on_quit() {
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        let mut input = ScriptedInput(
+            [key(KeyCode::Char('n')), key(KeyCode::Char('q'))].into(),
+        );
+        event_loop(&mut terminal, App::default(), &mut input).unwrap();
+        assert!(input.0.is_empty());
+    }
+
+    #[test]
+    pub fn test_keys_redraw_when_idle() {
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        let app = App {
+            low_power: true,
+            ..Default::default()
+        };
+        let mut input = ScriptedInput(
+            [
+                key(KeyCode::Char('n')),
+                Event::FocusLost,
+                key(KeyCode::Char('n')),
+                key(KeyCode::Char('q')),
+            ]
+            .into(),
+        );
+        event_loop(&mut terminal, app, &mut input).unwrap();
+        // the first frame, then one per key before the quit. The focus event
+        // waits for the next frame
+        assert_eq!(terminal.get_frame().count(), 3);
+    }
+
+    #[test]
+    pub fn test_ctrl_c_quits() {
+        let ctrl_c = Event::Key(KeyEvent::new(
+            KeyCode::Char('c'),
+            KeyModifiers::CONTROL,
+        ));
+        let mut app = App::default();
+        assert_eq!(update(&mut app, ctrl_c.clone()), vec![Effect::Quit]);
+        // and stops the loop before reading anything after it
+        let mut terminal = Terminal::new(TestBackend::new(80, 24)).unwrap();
+        let mut input = ScriptedInput([ctrl_c, key(KeyCode::Char('n'))].into());
+        event_loop(&mut terminal, App::default(), &mut input).unwrap();
+        assert_eq!(input.0.len(), 1);
+    }
+
+    #[test]
+    pub fn test_focus_transitions() {
+        let mut app = App::default();
+        assert_eq!(frame_interval(&app), FRAME_INTERVAL);
+
+        assert!(update(&mut app, Event::FocusLost).is_empty());
+        assert!(app.unfocused);
+        assert!(app.builder_view.proc_poller.is_paused());
+        assert_eq!(frame_interval(&app), IDLE_FRAME_INTERVAL);
+
+        // keys still work while unfocused
+        update(&mut app, key(KeyCode::Char('n')));
+        assert_eq!(app.tab_selected, SelectedTab::BirdsEyeView);
+
+        assert_eq!(update(&mut app, Event::FocusGained), vec![Effect::Redraw]);
+        assert!(!app.unfocused);
+        assert!(!app.builder_view.proc_poller.is_paused());
+        assert_eq!(frame_interval(&app), FRAME_INTERVAL);
+
+        let mut app = App {
+            low_power: true,
+            ..Default::default()
+        };
+        assert_eq!(frame_interval(&app), IDLE_FRAME_INTERVAL);
+        update(&mut app, Event::FocusGained);
+        assert_eq!(frame_interval(&app), IDLE_FRAME_INTERVAL);
+    }
+
+    #[test]
+    pub fn test_parse_interval() {
+        let parse = |s| parse_interval(s, MIN_INTERVAL, MAX_INTERVAL);
+        assert_eq!(parse("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse("5s"), Ok(Duration::from_secs(5)));
+        assert_eq!(parse("1.5"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse("60s"), Ok(MAX_INTERVAL));
+        assert!(parse("61s").is_err());
+        assert!(parse("5ms").is_err());
+        assert!(parse("-1s").is_err());
+        assert!(parse("NaN").is_err());
+        assert!(parse("fast").is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    pub fn test_configured_frame_interval() {
+        let mut app = App {
+            frame_interval: Some(Duration::from_millis(100)),
+            ..Default::default()
+        };
+        assert_eq!(frame_interval(&app), Duration::from_millis(100));
+        update(&mut app, Event::FocusLost);
+        assert_eq!(frame_interval(&app), IDLE_FRAME_INTERVAL);
+        assert_eq!(frame_interval(&App::default()), FRAME_INTERVAL);
+    }
+
+    #[test]
+    pub fn test_refresh_key_redraws() {
+        let mut app = App::default();
+        assert_eq!(
+            update(&mut app, key(KeyCode::Char('r'))),
+            vec![Effect::Redraw]
+        );
+    }
+
+    #[test]
+    pub fn test_sort_keys() {
+        use crate::proc_table::{ProcColumn, ProcSort};
+
+        let mut app = App::default();
+        update(&mut app, key(KeyCode::Char('4')));
+        update(&mut app, key(KeyCode::Char('4')));
+        assert_eq!(
+            app.builder_view.proc_table.sort,
+            ProcSort {
+                column: ProcColumn::PMem,
+                descending: true,
+            }
+        );
+        // no eighth column
+        update(&mut app, key(KeyCode::Char('8')));
+        assert_eq!(app.builder_view.proc_table.sort.column, ProcColumn::PMem);
+    }
+
+    #[test]
+    pub fn test_export_keys() {
+        use crate::export::ExportTarget;
+
+        let mut app = App::default();
+        // nothing selected to copy
+        assert!(update(&mut app, key(KeyCode::Char('y'))).is_empty());
+        assert!(app.builder_view.status_message.is_some());
+        assert_eq!(
+            update(&mut app, key(KeyCode::Char('Y'))),
+            vec![Effect::Export(ExportTarget::Clipboard)]
+        );
+        assert_eq!(
+            update(&mut app, key(KeyCode::Char('W'))),
+            vec![Effect::Export(ExportTarget::File)]
+        );
+    }
+
+    #[test]
+    pub fn test_table_navigation() {
+        use std::collections::BTreeSet;
+
+        use sysinfo::Pid;
+
+        use crate::{get_stats::ProcMetadata, proc_poller::BuilderSnapshot};
+
+        let procs: BTreeSet<_> = [30usize, 10, 20]
+            .map(|pid| {
+                ProcMetadata::fake(pid, "nixbld1")
+                    .with_cmd(&["cc", "-c", "foo.c"])
+            })
+            .into();
+        let mut app = App::default();
+        app.builder_view
+            .proc_poller
+            .publish(BuilderSnapshot::new([("nixbld1".into(), procs)].into()));
+        app.builder_view.state.select(vec!["nixbld1".to_string()]);
+        app.builder_view.proc_table.page_height = 2;
+        let selected = |app: &App| app.builder_view.proc_table.selected;
+
+        update(&mut app, key(KeyCode::Char('l')));
+        update(&mut app, key(KeyCode::Char('j')));
+        assert_eq!(selected(&app), Some(Pid::from(10)));
+        update(&mut app, key(KeyCode::PageDown));
+        assert_eq!(selected(&app), Some(Pid::from(30)));
+        update(&mut app, key(KeyCode::Char('k')));
+        assert_eq!(selected(&app), Some(Pid::from(20)));
+        update(&mut app, key(KeyCode::Char('g')));
+        assert_eq!(selected(&app), Some(Pid::from(10)));
+        // descending by pid: G is now the lowest pid
+        update(&mut app, key(KeyCode::Char('1')));
+        update(&mut app, key(KeyCode::Char('G')));
+        assert_eq!(selected(&app), Some(Pid::from(10)));
+        assert_eq!(
+            app.builder_view.state.selected(),
+            vec!["nixbld1".to_string()]
+        );
+        assert_eq!(
+            update(&mut app, key(KeyCode::Char('y'))),
+            vec![Effect::Copy("cc -c foo.c".to_string())]
+        );
+    }
+
+    #[test]
+    pub fn test_cancel_needs_confirmation() {
+        use crate::cancel::CancelPlan;
+
+        // nothing selected
+        let mut app = App::default();
+        assert!(update(&mut app, key(KeyCode::Char('x'))).is_empty());
+        assert!(app.builder_view.pending_cancel.is_none());
+        assert!(app.builder_view.status_message.is_some());
+
+        let plan = CancelPlan {
+            builder: "nixbld1".to_string(),
+            pids: vec![],
+        };
+        app.builder_view.pending_cancel = Some(plan.clone());
+        // any other key aborts, and is swallowed
+        assert!(update(&mut app, key(KeyCode::Char('q'))).is_empty());
+        assert!(app.builder_view.pending_cancel.is_none());
+
+        app.builder_view.pending_cancel = Some(plan.clone());
+        assert_eq!(
+            update(&mut app, key(KeyCode::Char('y'))),
+            vec![Effect::Cancel(plan)]
+        );
+        assert!(app.builder_view.pending_cancel.is_none());
+    }
+
+    #[test]
+    pub fn test_cancel_only_from_builder_view() {
+        use crate::cancel::CancelPlan;
+
+        let plan = CancelPlan {
+            builder: "nixbld1".to_string(),
+            pids: vec![],
+        };
+        // another tab, and the manual
+        for code in [KeyCode::Char('n'), KeyCode::Char('M')] {
+            let mut app = App::default();
+            app.builder_view.state.select(vec!["nixbld1".to_string()]);
+            update(&mut app, key(code));
+            assert!(!app.builder_view_visible());
+
+            assert!(update(&mut app, key(KeyCode::Char('x'))).is_empty());
+            assert!(app.builder_view.pending_cancel.is_none());
+            assert!(app.builder_view.status_message.is_none());
+
+            app.builder_view.pending_cancel = Some(plan.clone());
+            assert!(update(&mut app, key(KeyCode::Char('y'))).is_empty());
+            assert!(app.builder_view.pending_cancel.is_none());
+        }
     }
 }
@@ -0,0 +1,298 @@
+// `Y`/`W`: the builder view as plain text, for pasting into a bug or ticket.
+// Everything is written out in full (every builder, every process, the whole
+// command line) rather than what happens to fit on screen
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write as _,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    format::{format_bytes, format_duration},
+    get_stats::{builder_sort_key, ProcMetadata},
+};
+
+/// OSC 52 payloads (base64) bigger than this get dropped by terminals, so
+/// we write a file instead
+pub const CLIPBOARD_LIMIT: usize = 200 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportTarget {
+    Clipboard,
+    File,
+}
+
+/// strips escape sequences and other control characters, so a hostile
+/// command line can't mess with whatever the export is pasted into
+pub fn sanitize(s: &str) -> String {
+    strip_ansi_escapes::strip_str(s)
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect()
+}
+
+/// every busy builder and its processes, one indented line per process
+pub fn render_builders(
+    user_map: &HashMap<String, BTreeSet<ProcMetadata>>,
+    unix_time: u64,
+) -> String {
+    let mut builders: Vec<_> = user_map
+        .iter()
+        .filter(|(_, procs)| !procs.is_empty())
+        .collect();
+    builders.sort_by(|(x, _), (y, _)| {
+        builder_sort_key(x).cmp(&builder_sort_key(y))
+    });
+
+    let mut out = format!(
+        "# nix-btm builder view, exported at unix time {unix_time}, {} \
+         builders busy\n",
+        builders.len()
+    );
+    for (builder, procs) in builders {
+        let _ = writeln!(out, "{} ({})", sanitize(builder), procs.len());
+        for proc in procs {
+            let _ = write!(
+                out,
+                "  pid {} parent {} mem {} virt {} time {}",
+                proc.id,
+                proc.parent.map_or("-".to_string(), |p| p.to_string()),
+                format_bytes(proc.p_mem),
+                format_bytes(proc.v_mem),
+                format_duration(proc.run_time),
+            );
+            if !proc.env.is_empty() {
+                let _ = write!(out, " env {}", sanitize(&proc.env.join(" ")));
+            }
+            let _ = writeln!(out, ": {}", sanitize(&proc.cmd.join(" ")));
+        }
+    }
+    out
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | ((*b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// asks the terminal to put `text` on the system clipboard. Works over ssh,
+/// unlike shelling out to a clipboard tool
+pub fn osc52_sequence(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", base64(text.as_bytes()))
+}
+
+/// exports within the same second get "-1", "-2", ... appended, up to this
+const MAX_SAME_SECOND_EXPORTS: u32 = 100;
+
+/// writes `text` to a new "nix-btm-export-<unix time>.txt" in `dir`, never
+/// overwriting an earlier export
+pub fn write_file(
+    dir: &Path,
+    text: &str,
+    unix_time: u64,
+) -> io::Result<PathBuf> {
+    for n in 0..MAX_SAME_SECOND_EXPORTS {
+        let suffix = if n == 0 {
+            String::new()
+        } else {
+            format!("-{n}")
+        };
+        let path = dir.join(format!("nix-btm-export-{unix_time}{suffix}.txt"));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                file.write_all(text.as_bytes())?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "too many exports this second",
+    ))
+}
+
+/// exports the builder view and returns a status line saying where it went.
+/// `terminal` is where the OSC 52 sequence is written for the clipboard
+pub fn export(
+    user_map: &HashMap<String, BTreeSet<ProcMetadata>>,
+    target: ExportTarget,
+    terminal: &mut impl Write,
+    dir: &Path,
+) -> String {
+    let unix_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let text = render_builders(user_map, unix_time);
+    // base64 turns every 3 bytes into 4
+    let payload_len = 4 * text.len().div_ceil(3);
+    if target == ExportTarget::Clipboard && payload_len <= CLIPBOARD_LIMIT {
+        return match terminal
+            .write_all(osc52_sequence(&text).as_bytes())
+            .and_then(|()| terminal.flush())
+        {
+            Ok(()) => format!("copied {} lines", text.lines().count()),
+            Err(e) => format!("could not copy: {e}"),
+        };
+    }
+    let too_big = if target == ExportTarget::Clipboard {
+        "too big for the clipboard, "
+    } else {
+        ""
+    };
+    match write_file(dir, &text, unix_time) {
+        Ok(path) => format!("{too_big}wrote {}", path.display()),
+        Err(e) => format!("{too_big}could not write export: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use super::*;
+
+    fn proc(pid: usize, owner: &str, cmd: &[&str]) -> ProcMetadata {
+        ProcMetadata::fake(pid, owner)
+            .with_parent(1)
+            .with_mem(2048, 4096)
+            .with_run_time(61)
+            .with_cmd(cmd)
+    }
+
+    fn user_map() -> HashMap<String, BTreeSet<ProcMetadata>> {
+        [
+            (
+                "nixbld10".to_string(),
+                [proc(30, "nixbld10", &["make", "-j4"])].into(),
+            ),
+            (
+                "nixbld2".to_string(),
+                [
+                    proc(20, "nixbld2", &["bash", "-e", "builder.sh"]),
+                    proc(21, "nixbld2", &["evil\x1b]0;pwned\x07", "\x1b[2J"]),
+                ]
+                .into(),
+            ),
+            ("nixbld3".to_string(), BTreeSet::new()),
+        ]
+        .into()
+    }
+
+    #[test]
+    pub fn test_render_builders() {
+        let text = render_builders(&user_map(), 1700000000);
+        let expected = format!(
+            "# nix-btm builder view, exported at unix time 1700000000, 2 \
+             builders busy\n\
+             nixbld2 (2)\n  \
+             pid 20 parent 1 mem {mem} virt {virt} time {time}: bash -e \
+             builder.sh\n  \
+             pid 21 parent 1 mem {mem} virt {virt} time {time}: evil \n\
+             nixbld10 (1)\n  \
+             pid 30 parent 1 mem {mem} virt {virt} time {time}: make -j4\n",
+            mem = format_bytes(2048),
+            virt = format_bytes(4096),
+            time = format_duration(61),
+        );
+        assert_eq!(text, expected);
+        assert!(!text.contains('\x1b'));
+    }
+
+    #[test]
+    pub fn test_base64() {
+        for (input, expected) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64(input.as_bytes()), expected);
+        }
+        assert_eq!(osc52_sequence("foo"), "\x1b]52;c;Zm9v\x07");
+    }
+
+    #[test]
+    pub fn test_same_second_exports_dont_collide() {
+        let dir = env::temp_dir()
+            .join(format!("nix-btm-export-same-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let first = write_file(&dir, "a", 1700000000).unwrap();
+        let second = write_file(&dir, "b", 1700000000).unwrap();
+        let third = write_file(&dir, "c", 1700000000).unwrap();
+        assert_eq!(first, dir.join("nix-btm-export-1700000000.txt"));
+        assert_eq!(second, dir.join("nix-btm-export-1700000000-1.txt"));
+        assert_eq!(third, dir.join("nix-btm-export-1700000000-2.txt"));
+        assert_eq!(fs::read_to_string(first).unwrap(), "a");
+        assert_eq!(fs::read_to_string(second).unwrap(), "b");
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    pub fn test_export_targets() {
+        let dir = env::temp_dir()
+            .join(format!("nix-btm-export-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut terminal = vec![];
+        let status =
+            export(&user_map(), ExportTarget::Clipboard, &mut terminal, &dir);
+        assert_eq!(status, "copied 6 lines");
+        assert!(terminal.starts_with(b"\x1b]52;c;"));
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+        let mut terminal = vec![];
+        let status =
+            export(&user_map(), ExportTarget::File, &mut terminal, &dir);
+        assert!(terminal.is_empty());
+        let written: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(written.len(), 1);
+        let path = written[0].as_ref().unwrap().path();
+        assert_eq!(status, format!("wrote {}", path.display()));
+        assert!(fs::read_to_string(&path)
+            .unwrap()
+            .starts_with("# nix-btm builder view"));
+
+        // too big for the clipboard falls back to a file. The text alone
+        // would fit, its base64 doesn't
+        let huge_cmd = "x".repeat(CLIPBOARD_LIMIT * 7 / 8);
+        let huge: HashMap<_, _> = [(
+            "nixbld1".to_string(),
+            [proc(1, "nixbld1", &[&huge_cmd])].into(),
+        )]
+        .into();
+        assert!(render_builders(&huge, 0).len() < CLIPBOARD_LIMIT);
+        fs::remove_file(&path).unwrap();
+        let mut terminal = vec![];
+        let status =
+            export(&huge, ExportTarget::Clipboard, &mut terminal, &dir);
+        assert!(terminal.is_empty());
+        assert!(status.starts_with("too big for the clipboard, wrote"));
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,133 @@
+// every number that reaches the screen goes through here so that panes never
+// disagree about units or rounding. Sizes are always binary (KiB, MiB, ...)
+// with one decimal place.
+use ratatui::{style::Style, text::Span};
+
+const BYTE_UNITS: [&str; 7] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+
+pub fn format_bytes(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{bytes} B");
+    }
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    // promote anything that would round up to 1024.0 so we never print
+    // "1024.0 KiB" instead of "1.0 MiB"
+    while value >= 1023.95 && unit < BYTE_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{value:.1} {}", BYTE_UNITS[unit])
+}
+
+/// compact duration with at most two units, e.g. "45s", "3m07s", "2h05m",
+/// "1d04h"
+pub fn format_duration(secs: u64) -> String {
+    const MINUTE: u64 = 60;
+    const HOUR: u64 = 60 * MINUTE;
+    const DAY: u64 = 24 * HOUR;
+    if secs < MINUTE {
+        format!("{secs}s")
+    } else if secs < HOUR {
+        format!("{}m{:02}s", secs / MINUTE, secs % MINUTE)
+    } else if secs < DAY {
+        format!("{}h{:02}m", secs / HOUR, (secs % HOUR) / MINUTE)
+    } else {
+        format!("{}d{:02}h", secs / DAY, (secs % DAY) / HOUR)
+    }
+}
+
+/// display width in terminal cells: CJK and emoji take two, combining marks
+/// and zero width joiners take none
+pub fn display_width(s: &str) -> usize {
+    Span::raw(s).width()
+}
+
+/// cuts `s` down to at most `width` cells, ending in "…" if anything was
+/// dropped. Cuts only between graphemes, so accents stay on their letters
+/// and emoji sequences aren't split
+pub fn truncate_to_width(s: &str, width: usize) -> String {
+    if display_width(s) <= width {
+        return s.to_string();
+    }
+    let Some(budget) = width.checked_sub(1) else {
+        return String::new();
+    };
+    let mut out = String::new();
+    let mut used = 0;
+    for grapheme in Span::raw(s).styled_graphemes(Style::default()) {
+        let grapheme_width = display_width(grapheme.symbol);
+        if used + grapheme_width > budget {
+            break;
+        }
+        used += grapheme_width;
+        out.push_str(grapheme.symbol);
+    }
+    out.push('…');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KIB: u64 = 1024;
+    const MIB: u64 = 1024 * KIB;
+    const GIB: u64 = 1024 * MIB;
+
+    #[test]
+    pub fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0 B");
+        assert_eq!(format_bytes(1), "1 B");
+        assert_eq!(format_bytes(1023), "1023 B");
+        assert_eq!(format_bytes(KIB), "1.0 KiB");
+        assert_eq!(format_bytes(KIB + KIB / 2), "1.5 KiB");
+        assert_eq!(format_bytes(MIB - 1), "1.0 MiB");
+        assert_eq!(format_bytes(MIB), "1.0 MiB");
+        assert_eq!(format_bytes(GIB - 1), "1.0 GiB");
+        assert_eq!(format_bytes(GIB), "1.0 GiB");
+        assert_eq!(format_bytes(5 * GIB + 512 * MIB), "5.5 GiB");
+        assert_eq!(format_bytes(1024 * GIB), "1.0 TiB");
+        assert_eq!(format_bytes(u64::MAX), "16.0 EiB");
+    }
+
+    #[test]
+    pub fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(59), "59s");
+        assert_eq!(format_duration(60), "1m00s");
+        assert_eq!(format_duration(3 * 60 + 7), "3m07s");
+        assert_eq!(format_duration(3599), "59m59s");
+        assert_eq!(format_duration(3600), "1h00m");
+        assert_eq!(format_duration(2 * 3600 + 5 * 60 + 59), "2h05m");
+        assert_eq!(format_duration(86_399), "23h59m");
+        assert_eq!(format_duration(86_400), "1d00h");
+        assert_eq!(format_duration(86_400 + 4 * 3600), "1d04h");
+        assert_eq!(format_duration(400 * 86_400), "400d00h");
+        assert_eq!(format_duration(u64::MAX), "213503982334601d07h");
+    }
+
+    #[test]
+    pub fn test_truncate_to_width() {
+        assert_eq!(truncate_to_width("hello", 5), "hello");
+        assert_eq!(truncate_to_width("hello", 4), "hel…");
+        assert_eq!(truncate_to_width("hello", 1), "…");
+        assert_eq!(truncate_to_width("hello", 0), "");
+        // two cells per character, and never half of one
+        assert_eq!(truncate_to_width("日本語のパッケージ", 7), "日本語…");
+        assert_eq!(truncate_to_width("日本語のパッケージ", 8), "日本語…");
+        // the combining accent stays with its e
+        assert_eq!(truncate_to_width("cafe\u{301}-latte", 5), "cafe\u{301}…");
+        // a family emoji is a single two cell grapheme, so one fits in front
+        // of the ellipsis
+        let family = "👨\u{200d}👩\u{200d}👧";
+        let truncated = truncate_to_width(&format!("{family}{family}x"), 4);
+        assert_eq!(truncated, format!("{family}…"));
+        for s in ["שלום עולם", "ñ̃ö̈", "日本", "👍🏽👍🏽"]
+        {
+            for width in 0..8 {
+                assert!(display_width(&truncate_to_width(s, width)) <= width);
+            }
+        }
+    }
+}
@@ -1,13 +1,17 @@
-// note: bailing on btreemap because I want sorted by builder number, not string
+// processes are kept in BTreeSets so they come out in pid order. Builders
+// are sorted numerically (nixbld2 before nixbld10) with `builder_sort_key`
 use std::{
     cmp::Ordering,
-    collections::{hash_map::Entry, BTreeSet, HashMap, HashSet, VecDeque},
+    collections::{
+        btree_map, hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet,
+        VecDeque,
+    },
     hash::Hash,
     ops::Deref,
-    process::Command,
 };
 
-use procfs::process::Process as ProcFsProcess;
+#[cfg(target_os = "linux")]
+use procfs::process::{FDTarget, Process as ProcFsProcess};
 
 #[allow(clippy::unnecessary_literal_unwrap)]
 pub fn nll_todo<T>() -> T {
@@ -16,9 +20,15 @@ pub fn nll_todo<T>() -> T {
 
 use lazy_static::lazy_static;
 use ratatui::text::Text;
-use sysinfo::{Pid, Process, System, Users};
+use sysinfo::{Pid, Process, ProcessRefreshKind, System, UpdateKind, Users};
 use tui_tree_widget::TreeItem;
 
+use crate::{
+    names::NameRules,
+    nix_cli::nix,
+    store_path::{StorePath, StorePathError},
+};
+
 lazy_static! {
     /// This is an example for using doc comment attributes
     pub static ref NIX_USERS: HashSet<String> = {
@@ -27,6 +37,7 @@ lazy_static! {
     pub static ref USERS: Users = {
         Users::new_with_refreshed_list()
     };
+    static ref NAME_RULES: NameRules = NameRules::default();
     pub static ref SORTED_NIX_USERS: Vec<String> = {
         get_sorted_nix_users()
     };
@@ -41,15 +52,21 @@ pub fn get_nix_users(users: &Users) -> HashSet<String> {
         .collect()
 }
 
+/// orders builders by number ("nixbld2" before "nixbld10", "_nixbld1" on
+/// macos). Users that don't end in a number (e.g. lazily created by some
+/// installers) sort after the numbered ones, by name
+pub fn builder_sort_key(user: &str) -> (usize, &str) {
+    let num = user
+        .trim_start_matches('_')
+        .strip_prefix("nixbld")
+        .and_then(|num| num.parse().ok());
+    (num.unwrap_or(usize::MAX), user)
+}
+
 pub fn get_sorted_nix_users() -> Vec<String> {
     let mut nix_users: Vec<_> =
         Deref::deref(&NIX_USERS).iter().cloned().collect();
-    nix_users.sort_by(|x, y| {
-        let offset = if x.starts_with('_') { 7 } else { 6 };
-        let x_num: usize = x[offset..].parse().unwrap();
-        let y_num: usize = y[offset..].parse().unwrap();
-        x_num.partial_cmp(&y_num).unwrap()
-    });
+    nix_users.sort_by(|x, y| builder_sort_key(x).cmp(&builder_sort_key(y)));
     nix_users
 }
 
@@ -62,9 +79,68 @@ pub struct ProcMetadata {
     pub p_mem: u64,
     pub v_mem: u64,
     pub run_time: u64,
+    /// seconds since the epoch. Tells this process apart from a later one
+    /// that gets the same pid
+    pub start_time: u64,
     pub cmd: Vec<String>,
 }
 
+#[cfg(test)]
+impl ProcMetadata {
+    /// a process for tests: `pid` run by `owner`, with everything else zero
+    /// or empty until set with the `with_*` methods
+    pub fn fake(pid: usize, owner: &str) -> Self {
+        ProcMetadata {
+            id: Pid::from(pid),
+            owner: owner.to_string(),
+            env: vec![],
+            parent: None,
+            p_mem: 0,
+            v_mem: 0,
+            run_time: 0,
+            start_time: 0,
+            cmd: vec![],
+        }
+    }
+
+    pub fn with_parent(self, parent: usize) -> Self {
+        ProcMetadata {
+            parent: Some(Pid::from(parent)),
+            ..self
+        }
+    }
+
+    pub fn with_mem(self, p_mem: u64, v_mem: u64) -> Self {
+        ProcMetadata {
+            p_mem,
+            v_mem,
+            ..self
+        }
+    }
+
+    pub fn with_run_time(self, run_time: u64) -> Self {
+        ProcMetadata { run_time, ..self }
+    }
+
+    pub fn with_start_time(self, start_time: u64) -> Self {
+        ProcMetadata { start_time, ..self }
+    }
+
+    pub fn with_cmd(self, cmd: &[&str]) -> Self {
+        ProcMetadata {
+            cmd: cmd.iter().map(|s| s.to_string()).collect(),
+            ..self
+        }
+    }
+
+    pub fn with_env(self, env: &[&str]) -> Self {
+        ProcMetadata {
+            env: env.iter().map(|s| s.to_string()).collect(),
+            ..self
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct DrvRoot {
     pub drv: Drv,
@@ -87,6 +163,15 @@ pub struct Drv {
     pub human_readable_drv: String,
 }
 
+impl Drv {
+    pub fn new(path: &StorePath) -> Self {
+        Drv {
+            drv: path.to_string(),
+            human_readable_drv: path.human_readable().to_string(),
+        }
+    }
+}
+
 impl std::hash::Hash for Drv {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.drv.hash(state);
@@ -144,6 +229,7 @@ pub fn from_proc(proc: &Process) -> Option<ProcMetadata> {
         p_mem: proc.memory(),
         v_mem: proc.virtual_memory(),
         run_time: proc.run_time(),
+        start_time: proc.start_time(),
         cmd: proc.cmd().into(),
     })
 }
@@ -189,7 +275,7 @@ pub fn get_active_users_and_pids() -> HashMap<String, BTreeSet<ProcMetadata>> {
 #[derive(Clone, Debug)]
 pub struct DrvNode {
     pub drv: Drv,
-    pub children: HashSet<String>,
+    pub children: BTreeSet<String>,
 }
 
 impl PartialEq for DrvNode {
@@ -211,13 +297,13 @@ impl std::hash::Hash for DrvNode {
 #[derive(Debug, Clone, Eq)]
 pub struct TreeNode {
     pid: Pid,
-    children: HashSet<TreeNode>,
+    children: BTreeSet<TreeNode>,
 }
 
 #[derive(Debug, Clone)]
 pub struct ThickerTreeNode<'a> {
     proc: &'a ProcMetadata,
-    children: HashSet<ThickerTreeNode<'a>>,
+    children: BTreeSet<ThickerTreeNode<'a>>,
 }
 
 impl<'a> PartialEq for ThickerTreeNode<'a> {
@@ -234,6 +320,18 @@ impl<'a> Hash for ThickerTreeNode<'a> {
     }
 }
 
+impl<'a> PartialOrd for ThickerTreeNode<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> Ord for ThickerTreeNode<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.proc.cmp(other.proc)
+    }
+}
+
 impl PartialEq for TreeNode {
     fn eq(&self, other: &Self) -> bool {
         self.pid == other.pid
@@ -246,6 +344,18 @@ impl Hash for TreeNode {
     }
 }
 
+impl PartialOrd for TreeNode {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TreeNode {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.pid.cmp(&other.pid)
+    }
+}
+
 pub fn merge_trees(t1: &mut TreeNode, t2: &TreeNode) {
     let t1_cur = t1;
     let t2_cur = t2;
@@ -310,15 +420,15 @@ pub fn get_parent(
 pub fn construct_tree(
     procs: HashSet<Pid>,
     pid_map: &mut HashMap<Pid, ProcMetadata>,
-) -> HashMap<Pid, TreeNode> {
-    let mut roots = HashMap::<Pid, TreeNode>::new();
+) -> BTreeMap<Pid, TreeNode> {
+    let mut roots = BTreeMap::<Pid, TreeNode>::new();
     'top: for pid in procs {
         let mut cur_pid = pid.clone();
-        let mut proc_subtree: HashSet<TreeNode> = HashSet::new();
+        let mut proc_subtree: BTreeSet<TreeNode> = BTreeSet::new();
         loop {
             match get_parent(cur_pid, pid_map) {
                 PidParent::IsAlive(p_pid) => {
-                    let mut new_proc_subtree = HashSet::new();
+                    let mut new_proc_subtree = BTreeSet::new();
                     new_proc_subtree.insert(TreeNode {
                         pid: cur_pid.clone(),
                         children: proc_subtree,
@@ -334,7 +444,7 @@ pub fn construct_tree(
                         // iterating on
                         children: proc_subtree,
                     };
-                    if let Entry::Vacant(e) = roots.entry(root_pid) {
+                    if let btree_map::Entry::Vacant(e) = roots.entry(root_pid) {
                         e.insert(new_tree_root);
                     } else {
                         let cur_root_tree = roots.get_mut(&root_pid).unwrap();
@@ -361,34 +471,49 @@ pub fn gen_ui_by_parent_proc(root: &TreeNode) -> Vec<TreeItem<'_, String>> {
     todo!()
 }
 
+/// "nixbld1 (3)", followed by the short name of what it's building when a
+/// process has nix's `name` variable in its environment. Reading another
+/// user's environment usually needs root, so often it's just the count
+fn builder_label(user: &str, procs: &BTreeSet<ProcMetadata>) -> String {
+    let mut label = format!("{user} ({})", procs.len());
+    let building = procs
+        .iter()
+        .flat_map(|proc| &proc.env)
+        .find_map(|var| var.strip_prefix("name="));
+    if let Some(name) = building {
+        label.push(' ');
+        label.push_str(&NAME_RULES.shorten(name).to_string());
+    }
+    label
+}
+
 // TODO there's definitely some optimization here to not query/process every
 // time probably need to introduce some global state that we tweak every time
 // utilizing refcell
 pub fn gen_ui_by_nix_builder(
     user_map: &HashMap<String, BTreeSet<ProcMetadata>>,
-) -> Vec<TreeItem<'_, String>> {
+) -> Vec<TreeItem<'static, String>> {
     let mut r_vec = Vec::new();
 
     let mut sorted_user_map: Vec<_> = user_map.iter().collect();
-
-    // TODO refactor to a function, pass in to this function, ...
-    sorted_user_map.sort_by(|&x, &y| {
-        let offset = if x.0.starts_with('_') { 7 } else { 6 };
-        let x_num: usize = x.0[offset..].parse().unwrap();
-        let y_num: usize = y.0[offset..].parse().unwrap();
-        x_num.partial_cmp(&y_num).unwrap()
+    sorted_user_map.sort_by(|(x, _), (y, _)| {
+        builder_sort_key(x).cmp(&builder_sort_key(y))
     });
 
     for (user, map) in sorted_user_map {
-        let mut leaves = Vec::new();
-        for pid in map {
-            // gross there's definitely a better way
-            let t_pid = Text::from(pid.id.to_string());
-            leaves.push(TreeItem::new_leaf(pid.id.to_string(), t_pid));
+        let leaves = map
+            .iter()
+            .map(|proc| {
+                let id = proc.id.to_string();
+                TreeItem::new_leaf(id.clone(), Text::from(id))
+            })
+            .collect();
+        let t_user = Text::from(builder_label(user, map));
+        // the set is keyed by pid so leaves are unique, but a missing
+        // builder beats a crashed draw if this ever fails
+        if let Ok(root) = TreeItem::new(user.clone(), t_user, leaves) {
+            r_vec.push(root);
         }
-        let t_user = Text::from(format!("{} ({})", user.clone(), map.len()));
-        let root = TreeItem::new(user.clone(), t_user, leaves).unwrap();
-        r_vec.push(root);
     }
 
     r_vec
@@ -400,7 +525,7 @@ pub fn convert_to_thicker_tree_node<'a>(
 ) -> ThickerTreeNode<'a> {
     let mut root = ThickerTreeNode {
         proc: map.get(&tree_node.pid).unwrap(),
-        children: HashSet::new(),
+        children: BTreeSet::new(),
     };
     for child in &tree_node.children {
         let tmp = convert_to_thicker_tree_node(child, map);
@@ -410,7 +535,7 @@ pub fn convert_to_thicker_tree_node<'a>(
 }
 
 pub fn dump_pids(
-    tree_nodes: &HashMap<Pid, TreeNode>,
+    tree_nodes: &BTreeMap<Pid, TreeNode>,
     map: &HashMap<Pid, ProcMetadata>,
 ) {
     for tree_node in tree_nodes {
@@ -422,14 +547,14 @@ pub fn dump_pids(
 pub fn strip_tf_outta_tree(
     tree_node: TreeNode,
     _pid_map: &HashMap<Pid, ProcMetadata>,
-) -> HashMap<Pid, TreeNode> {
+) -> BTreeMap<Pid, TreeNode> {
     // go two levels deeper
     // pid_map passed in exactly for this purpose
     // TODO add in an assert
     //                 cmd: [ "nix-daemon", "--daemon",
     //                  "/run/current-system/systemd/lib/systemd/systemd",
     let real_roots = tree_node.children.into_iter().next().unwrap().children;
-    let mut root_map = HashMap::new();
+    let mut root_map = BTreeMap::new();
     real_roots.into_iter().for_each(|root| {
         root_map.insert(root.pid, root);
     });
@@ -438,79 +563,168 @@ pub fn strip_tf_outta_tree(
 
 // function that converts string of form
 // "/nix/var/log/nix/drvs/z4/ps207hnvyh0lsrlmgkqyyfj3bbf37l-helix-24.03.drv.bz2"
-// to string of form
+// to the store path
 // "/nix/store/z4ps207hnvyh0lsrlmgkqyyfj3bbf37l-helix-24.03.drv"
-fn bz2_to_drv(input: &str) -> String {
-    let mut result = "/nix/store/".to_string();
-    for ele in input.split('/') {
-        match ele {
-            "nix" | "var" | "log" | "drvs" => {}
-            s => result.push_str(s),
-        }
+fn bz2_to_drv(input: &str) -> Result<StorePath, StorePathError> {
+    let (prefix, base) = input
+        .strip_prefix("/nix/var/log/nix/drvs/")
+        .and_then(|rest| rest.split_once('/'))
+        .ok_or_else(|| StorePathError::NotInStore(input.to_string()))?;
+    let base = base.strip_suffix(".bz2").unwrap_or(base);
+    StorePath::from_base_name(&format!("{prefix}{base}"))
+}
+
+/// the first argument that names a derivation, either directly or by its
+/// build log
+fn drv_in_args(args: &[String]) -> Option<StorePath> {
+    args.iter().find_map(|arg| {
+        bz2_to_drv(arg)
+            .ok()
+            .or_else(|| StorePath::parse(arg).ok().filter(StorePath::is_drv))
+    })
+}
+
+/// nix keeps the build log of the derivation it's building open, so the
+/// drv can be read off the builder's fds
+#[cfg(target_os = "linux")]
+fn drv_from_open_logs(pid: Pid) -> Option<StorePath> {
+    let proc = ProcFsProcess::new(pid.as_u32() as i32).ok()?;
+    proc.fd().ok()?.flatten().find_map(|fd| match fd.target {
+        // not a log for a drv we recognize, keep looking
+        FDTarget::Path(path) => bz2_to_drv(path.to_str()?).ok(),
+        _ => None,
+    })
+}
+
+/// the parts of <sys/proc_info.h> that libc doesn't declare
+#[cfg(target_os = "macos")]
+mod proc_info {
+    pub const PROC_PIDLISTFDS: libc::c_int = 1;
+    pub const PROC_PIDFDVNODEPATHINFO: libc::c_int = 2;
+    pub const PROX_FDTYPE_VNODE: u32 = 1;
+
+    /// `struct proc_fdinfo`
+    #[repr(C)]
+    #[derive(Debug, Clone, Copy, Default)]
+    pub struct FdInfo {
+        pub fd: i32,
+        pub fd_type: u32,
     }
-    result.chars().take(result.len() - 4).collect()
-}
-
-fn drv_to_readable_drv(input: &str, has_postfix: bool) -> String {
-    let mut result = "".to_string();
-    for ele in input.split('/') {
-        // println!("ele: {}", ele);
-        match ele {
-            "nix" | "store" | "" => {}
-            s => {
-                let mut start_recording = false;
-                for c in s.chars() {
-                    // println!("C: {}", c);
-                    if !start_recording {
-                        if c == '-' {
-                            start_recording = true;
-                        }
-                    } else {
-                        result.push(c);
-                    }
-                }
-                let offset = if has_postfix { 4 } else { 0 };
-                return result.chars().take(result.len() - offset).collect();
-            }
-        }
+
+    /// `struct proc_fileinfo`
+    #[repr(C)]
+    pub struct FileInfo {
+        open_flags: u32,
+        status: u32,
+        offset: libc::off_t,
+        file_type: i32,
+        guard_flags: u32,
     }
-    unreachable!()
-}
-
-// TODO error handling
-// TODO macos support
-pub fn create_drv_root(root: TreeNode) -> DrvRoot {
-    let root_pid = root.pid;
-    // this can totally fail
-    let proc = ProcFsProcess::new(root_pid.as_u32() as i32).unwrap();
-    let fds = proc.fd().unwrap();
-    for fd in fds {
-        let Ok(fd) = fd else { continue };
-        match fd.target {
-            procfs::process::FDTarget::Path(path) => {
-                if path.to_str().unwrap().starts_with("/nix/var/log/nix/drvs/")
-                {
-                    let drv_name = bz2_to_drv(path.to_str().unwrap());
-                    let readable = drv_to_readable_drv(&drv_name, true);
-                    return DrvRoot {
-                        drv: Drv {
-                            drv: drv_name,
-                            human_readable_drv: readable,
-                        },
-                        procs: root,
-                    };
-                }
-            }
-            _ => continue,
-        }
+
+    /// `struct vnode_fdinfowithpath`
+    #[repr(C)]
+    pub struct VnodeFdInfoWithPath {
+        pub file: FileInfo,
+        pub vnode: libc::vnode_info_path,
     }
-    unreachable!()
 }
 
-pub fn get_drvs(map: HashMap<Pid, TreeNode>) -> HashMap<Pid, DrvRoot> {
+/// same as on Linux, but the fds come from libproc: list them with
+/// PROC_PIDLISTFDS, then ask for the path of each vnode
+#[cfg(target_os = "macos")]
+fn drv_from_open_logs(pid: Pid) -> Option<StorePath> {
+    use std::{
+        ffi::CStr,
+        mem::{size_of, MaybeUninit},
+        ptr,
+    };
+
+    use proc_info::*;
+
+    let pid = pid.as_u32() as libc::c_int;
+    // SAFETY: with no buffer, proc_pidinfo only reports the size it needs
+    let needed = unsafe {
+        libc::proc_pidinfo(pid, PROC_PIDLISTFDS, 0, ptr::null_mut(), 0)
+    };
+    if needed <= 0 {
+        return None;
+    }
+    let mut fds =
+        vec![FdInfo::default(); needed as usize / size_of::<FdInfo>()];
+    // SAFETY: the buffer is exactly as many bytes as we say it is
+    let filled = unsafe {
+        libc::proc_pidinfo(
+            pid,
+            PROC_PIDLISTFDS,
+            0,
+            fds.as_mut_ptr().cast(),
+            (fds.len() * size_of::<FdInfo>()) as libc::c_int,
+        )
+    };
+    if filled <= 0 {
+        return None;
+    }
+    fds.truncate(filled as usize / size_of::<FdInfo>());
+
+    fds.iter()
+        .filter(|fd| fd.fd_type == PROX_FDTYPE_VNODE)
+        .find_map(|fd| {
+            let mut info = MaybeUninit::<VnodeFdInfoWithPath>::zeroed();
+            let size = size_of::<VnodeFdInfoWithPath>() as libc::c_int;
+            // SAFETY: `info` is `size` bytes
+            let filled = unsafe {
+                libc::proc_pidfdinfo(
+                    pid,
+                    fd.fd,
+                    PROC_PIDFDVNODEPATHINFO,
+                    info.as_mut_ptr().cast(),
+                    size,
+                )
+            };
+            if filled != size {
+                return None;
+            }
+            // SAFETY: zeroed is already a valid value, and the kernel
+            // filled it in
+            let info = unsafe { info.assume_init() };
+            // SAFETY: the kernel NUL terminates vip_path
+            let path =
+                unsafe { CStr::from_ptr(info.vnode.vip_path.as_ptr().cast()) };
+            bz2_to_drv(path.to_str().ok()?).ok()
+        })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn drv_from_open_logs(_pid: Pid) -> Option<StorePath> {
+    None
+}
+
+fn drv_from_cmdline(pid: Pid) -> Option<StorePath> {
+    let mut sys = System::new();
+    sys.refresh_process_specifics(
+        pid,
+        ProcessRefreshKind::new().with_cmd(UpdateKind::Always),
+    );
+    drv_in_args(sys.process(pid)?.cmd())
+}
+
+/// the derivation `pid` is building. None if it exited or we can't tell
+pub fn drv_for_builder_pid(pid: Pid) -> Option<Drv> {
+    drv_from_open_logs(pid)
+        .or_else(|| drv_from_cmdline(pid))
+        .map(|path| Drv::new(&path))
+}
+
+pub fn create_drv_root(root: TreeNode) -> Option<DrvRoot> {
+    let drv = drv_for_builder_pid(root.pid)?;
+    Some(DrvRoot::new(drv, root))
+}
+
+/// roots we couldn't attribute to a drv are left out
+pub fn get_drvs(map: BTreeMap<Pid, TreeNode>) -> BTreeMap<Pid, DrvRoot> {
     map.into_iter()
-        .map(|(k, v)| (k, create_drv_root(v)))
-        .collect::<HashMap<_, _>>()
+        .filter_map(|(k, v)| Some((k, create_drv_root(v)?)))
+        .collect::<BTreeMap<_, _>>()
 }
 
 #[derive(Clone, Debug)]
@@ -528,54 +742,50 @@ pub fn invoke_why_depends(
     drv1: &Drv,
     drv2: &Drv,
 ) -> Option<(HashMap<String, DrvNode>, String)> {
-    let output = Command::new("nix")
-        .arg("why-depends")
-        .arg(&drv1.drv)
-        .arg(&drv2.drv)
-        .output()
-        .expect("Failed to execute command");
+    let output = nix(&["why-depends", &drv1.drv, &drv2.drv]).ok()?;
 
     let mut cur_node_id: Option<String> = None;
     let mut root = None;
     let mut all_nodes = HashMap::new();
 
-    if output.status.success() {
-        let path = strip_ansi_escapes::strip_str(
-            String::from_utf8_lossy(&output.stdout).trim(),
-        )
-        .to_string()
-        .replace(['└', '─'], "")
-        .trim()
-        .to_string();
-        if path.contains("does not depend on") {
-            return None;
-        }
-
-        for line in path.lines() {
-            let drv = parse_drv(line);
-            match cur_node_id {
-                Some(tree_inner) => {
-                    let new_node = DrvNode {
-                        drv,
-                        children: HashSet::default(),
-                    };
-                    let mut cur_node: DrvNode =
-                        all_nodes.remove(&tree_inner).unwrap();
-                    cur_node.children.insert(new_node.drv.drv.clone());
-                    all_nodes.insert(tree_inner, cur_node);
+    let path = strip_ansi_escapes::strip_str(
+        String::from_utf8_lossy(&output.stdout).trim(),
+    )
+    .to_string()
+    .replace(['└', '─'], "")
+    .trim()
+    .to_string();
+    if path.contains("does not depend on") {
+        return None;
+    }
 
-                    cur_node_id = Some(new_node.drv.drv.clone());
-                    all_nodes.insert(new_node.drv.drv.clone(), new_node);
-                }
-                None => {
-                    root = Some(drv.drv.clone());
-                    let new_node = DrvNode {
-                        drv,
-                        children: HashSet::new(),
-                    };
-                    cur_node_id = Some(new_node.drv.drv.clone());
-                    all_nodes.insert(new_node.drv.drv.clone(), new_node);
-                }
+    for line in path.lines() {
+        // don't build a tree out of output we don't understand
+        let Ok(drv) = parse_drv(line) else {
+            return None;
+        };
+        match cur_node_id {
+            Some(tree_inner) => {
+                let new_node = DrvNode {
+                    drv,
+                    children: BTreeSet::default(),
+                };
+                let mut cur_node: DrvNode =
+                    all_nodes.remove(&tree_inner).unwrap();
+                cur_node.children.insert(new_node.drv.drv.clone());
+                all_nodes.insert(tree_inner, cur_node);
+
+                cur_node_id = Some(new_node.drv.drv.clone());
+                all_nodes.insert(new_node.drv.drv.clone(), new_node);
+            }
+            None => {
+                root = Some(drv.drv.clone());
+                let new_node = DrvNode {
+                    drv,
+                    children: BTreeSet::new(),
+                };
+                cur_node_id = Some(new_node.drv.drv.clone());
+                all_nodes.insert(new_node.drv.drv.clone(), new_node);
             }
         }
     }
@@ -583,11 +793,8 @@ pub fn invoke_why_depends(
     root.map(|t| (all_nodes, t))
 }
 
-fn parse_drv(line: &str) -> Drv {
-    Drv {
-        drv: line.to_string(),
-        human_readable_drv: drv_to_readable_drv(line, false),
-    }
+fn parse_drv(line: &str) -> Result<Drv, StorePathError> {
+    StorePath::parse(line.trim()).map(|path| Drv::new(&path))
 }
 
 fn dump_dep_tree((nodes, root_id): &(HashMap<String, DrvNode>, String)) {
@@ -616,6 +823,10 @@ pub fn create_dep_tree(
     input_drvs: HashSet<&Drv>,
 ) -> Vec<(HashMap<String, DrvNode>, String)> {
     let mut roots: Vec<(HashMap<String, DrvNode>, String)> = Vec::new();
+    // pair drvs up in a fixed order so the same builds always produce the
+    // same roots in the same order
+    let mut input_drvs: Vec<_> = input_drvs.into_iter().collect();
+    input_drvs.sort();
 
     for drv1 in &input_drvs {
         for drv2 in &input_drvs {
@@ -740,6 +951,176 @@ pub fn construct_everything() {
 
 #[cfg(test)]
 mod tests {
+    use sysinfo::Pid;
+
+    use std::collections::{BTreeSet, HashMap};
+
+    use super::{
+        builder_label, builder_sort_key, bz2_to_drv, drv_in_args,
+        gen_ui_by_nix_builder, merge_trees, parse_drv, ProcMetadata, TreeNode,
+    };
+
+    /// a single root-to-leaf path of pids
+    fn chain(pids: &[usize]) -> TreeNode {
+        pids.iter()
+            .rev()
+            .fold(None, |child: Option<TreeNode>, &pid| {
+                Some(TreeNode {
+                    pid: Pid::from(pid),
+                    children: child.into_iter().collect(),
+                })
+            })
+            .unwrap()
+    }
+
+    #[test]
+    pub fn test_merge_trees_is_order_independent() {
+        let paths = [vec![1, 2, 3], vec![1, 2, 4], vec![1, 5], vec![1, 6, 7]];
+        let merged = |order: &[usize]| {
+            let mut root = chain(&paths[order[0]]);
+            for &i in &order[1..] {
+                merge_trees(&mut root, &chain(&paths[i]));
+            }
+            format!("{root:?}")
+        };
+        let expected = merged(&[0, 1, 2, 3]);
+        assert_eq!(merged(&[3, 2, 1, 0]), expected);
+        assert_eq!(merged(&[2, 0, 3, 1]), expected);
+    }
+
+    fn user_map(
+        users: &[(&str, &[usize])],
+    ) -> HashMap<String, BTreeSet<ProcMetadata>> {
+        users
+            .iter()
+            .map(|(user, pids)| {
+                let procs = pids
+                    .iter()
+                    .map(|pid| ProcMetadata::fake(*pid, user))
+                    .collect();
+                (user.to_string(), procs)
+            })
+            .collect()
+    }
+
+    fn identifiers(
+        user_map: &HashMap<String, BTreeSet<ProcMetadata>>,
+    ) -> Vec<(String, usize)> {
+        gen_ui_by_nix_builder(user_map)
+            .iter()
+            .map(|item| (item.identifier().clone(), item.children().len()))
+            .collect()
+    }
+
+    #[test]
+    pub fn test_builder_label() {
+        let idle = BTreeSet::new();
+        assert_eq!(builder_label("nixbld1", &idle), "nixbld1 (0)");
+        let building: BTreeSet<_> = [
+            ProcMetadata::fake(1, "nixbld1"),
+            ProcMetadata::fake(2, "nixbld1").with_env(&[
+                "HOME=/homeless-shelter",
+                "name=python3.11-numpy-1.26.4",
+            ]),
+        ]
+        .into();
+        assert_eq!(
+            builder_label("nixbld1", &building),
+            "nixbld1 (2) [python3.11] numpy-1.26.4"
+        );
+    }
+
+    #[test]
+    pub fn test_gen_ui_by_nix_builder_never_panics() {
+        assert!(identifiers(&user_map(&[])).is_empty());
+        assert_eq!(
+            identifiers(&user_map(&[("nixbld1", &[])])),
+            vec![("nixbld1".to_string(), 0)]
+        );
+        // the same pid under two builders
+        assert_eq!(
+            identifiers(&user_map(&[("nixbld1", &[7, 8]), ("nixbld2", &[7])])),
+            vec![("nixbld1".to_string(), 2), ("nixbld2".to_string(), 1)]
+        );
+        assert_eq!(
+            identifiers(&user_map(&[
+                ("nixbld10", &[1]),
+                ("nixbld-lazy", &[2]),
+                ("nixbld2", &[3]),
+                ("x", &[]),
+            ]))
+            .into_iter()
+            .map(|(user, _)| user)
+            .collect::<Vec<_>>(),
+            vec!["nixbld2", "nixbld10", "nixbld-lazy", "x"]
+        );
+    }
+
+    #[test]
+    pub fn test_builder_sort_key() {
+        let mut users = vec!["_nixbld10", "nixbld", "_nixbld9", "nixbld1"];
+        users.sort_by_key(|u| builder_sort_key(u));
+        assert_eq!(users, vec!["nixbld1", "_nixbld9", "_nixbld10", "nixbld"]);
+    }
+
+    #[test]
+    pub fn test_drv_entry_points_validate() {
+        let drv = bz2_to_drv(
+            "/nix/var/log/nix/drvs/z4/\
+             ps207hnvyh0lsrlmgkqyyfj3bbf37l-helix-24.03.drv.bz2",
+        )
+        .unwrap();
+        assert_eq!(
+            drv.to_string(),
+            "/nix/store/z4ps207hnvyh0lsrlmgkqyyfj3bbf37l-helix-24.03.drv"
+        );
+        assert!(bz2_to_drv("/nix/var/log/nix/drvs/z4/garbage.bz2").is_err());
+        assert!(bz2_to_drv("/tmp/build.log").is_err());
+        assert_eq!(super::Drv::new(&drv).human_readable_drv, "helix-24.03");
+
+        let parsed = parse_drv(
+            "  /nix/store/8bdd933v69w05k5v8hfcq74bi1f9545k-openssl-3.0.13 ",
+        )
+        .unwrap();
+        assert_eq!(parsed.human_readable_drv, "openssl-3.0.13");
+        assert!(
+            parse_drv("/nix/store/8BDD933V69W05K5V8HFCQ74BI1F9545K-x").is_err()
+        );
+        assert!(parse_drv("does not depend on").is_err());
+    }
+
+    #[test]
+    pub fn test_drv_in_args() {
+        let args = |args: &[&str]| {
+            let args: Vec<_> = args.iter().map(|s| s.to_string()).collect();
+            drv_in_args(&args).map(|drv| drv.to_string())
+        };
+        let drv = "/nix/store/z4ps207hnvyh0lsrlmgkqyyfj3bbf37l-helix-24.03.drv";
+        assert_eq!(
+            args(&["nix-store", "--realise", drv]).as_deref(),
+            Some(drv)
+        );
+        assert_eq!(
+            args(&[
+                "tee",
+                "/nix/var/log/nix/drvs/z4/\
+                 ps207hnvyh0lsrlmgkqyyfj3bbf37l-helix-24.03.drv.bz2",
+            ])
+            .as_deref(),
+            Some(drv)
+        );
+        // outputs and scripts in the store aren't drvs
+        assert_eq!(
+            args(&[
+                "bash",
+                "/nix/store/z4ps207hnvyh0lsrlmgkqyyfj3bbf37l-helix-24.03",
+                "/nix/store/z4ps207hnvyh0lsrlmgkqyyfj3bbf37l-builder.sh/x.drv",
+            ]),
+            None
+        );
+        assert_eq!(args(&[]), None);
+    }
+
     // TODO fix test so it can run on any computer. This requires pre-fetching
     // the drvs
     #[test]
@@ -0,0 +1,163 @@
+// every non ascii glyph we draw goes through an `IconSet` so that terminals
+// without emoji fonts (the linux console, some CI logs, older PuTTY) get
+// something readable instead of tofu boxes
+use std::env;
+
+use crate::format::display_width;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IconSet {
+    pub builder_tab: &'static str,
+    pub birds_eye_tab: &'static str,
+    /// header of the run time column
+    pub run_time: &'static str,
+    /// appended to a title while work is in flight
+    pub ellipsis: &'static str,
+    /// appended to the header of the column a table is sorted by
+    pub sort_ascending: &'static str,
+    pub sort_descending: &'static str,
+    /// next to the title, colored by how healthy the nix daemon looks
+    pub health: &'static str,
+}
+
+impl Default for IconSet {
+    fn default() -> Self {
+        Self::emoji()
+    }
+}
+
+impl IconSet {
+    pub const fn emoji() -> Self {
+        IconSet {
+            builder_tab: "👷",
+            birds_eye_tab: "🦅",
+            run_time: "⏰",
+            ellipsis: "…",
+            sort_ascending: "▲",
+            sort_descending: "▼",
+            health: "●",
+        }
+    }
+
+    pub const fn ascii() -> Self {
+        IconSet {
+            builder_tab: "[B]",
+            birds_eye_tab: "[E]",
+            run_time: "time",
+            ellipsis: "...",
+            sort_ascending: "^",
+            sort_descending: "v",
+            health: "*",
+        }
+    }
+
+    /// plain text only: no decorations at all, words where a column needs a
+    /// header
+    pub const fn none() -> Self {
+        IconSet {
+            builder_tab: "",
+            birds_eye_tab: "",
+            run_time: "time",
+            ellipsis: "...",
+            sort_ascending: "^",
+            sort_descending: "v",
+            health: "",
+        }
+    }
+
+    /// `emoji`, `ascii`, `none`, or `auto` to guess from the environment
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "emoji" => Some(Self::emoji()),
+            "ascii" => Some(Self::ascii()),
+            "none" => Some(Self::none()),
+            "auto" => Some(Self::detect()),
+            _ => None,
+        }
+    }
+
+    pub fn detect() -> Self {
+        Self::detect_from(|key| env::var(key).ok())
+    }
+
+    /// best effort guess: the linux console and dumb terminals can't draw
+    /// emoji, and neither can anything without a UTF-8 locale
+    pub fn detect_from(var: impl Fn(&str) -> Option<String>) -> Self {
+        if matches!(var("TERM").as_deref(), Some("linux" | "dumb" | "vt100")) {
+            return Self::ascii();
+        }
+        // same precedence as setlocale(3)
+        let locale = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .into_iter()
+            .find_map(|key| var(key).filter(|value| !value.is_empty()));
+        match locale {
+            Some(locale) => {
+                let locale = locale.to_ascii_lowercase();
+                if locale.contains("utf-8") || locale.contains("utf8") {
+                    Self::emoji()
+                } else {
+                    Self::ascii()
+                }
+            }
+            None => Self::ascii(),
+        }
+    }
+}
+
+/// pads `s` with spaces to `width` terminal cells. Emoji are two cells wide,
+/// so `str::len` and `chars().count()` are both wrong here
+pub fn pad_to_width(s: &str, width: usize) -> String {
+    let padding = width.saturating_sub(display_width(s));
+    format!("{s}{}", " ".repeat(padding))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn detect(vars: &[(&str, &str)]) -> IconSet {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        IconSet::detect_from(|key| vars.get(key).map(|v| v.to_string()))
+    }
+
+    #[test]
+    pub fn test_detect() {
+        let utf8 = ("LANG", "en_US.UTF-8");
+        assert_eq!(detect(&[]), IconSet::ascii());
+        assert_eq!(detect(&[utf8]), IconSet::emoji());
+        assert_eq!(detect(&[utf8, ("TERM", "linux")]), IconSet::ascii());
+        assert_eq!(detect(&[("LANG", "C")]), IconSet::ascii());
+        assert_eq!(detect(&[utf8, ("LC_ALL", "C")]), IconSet::ascii());
+        assert_eq!(
+            detect(&[("LC_ALL", ""), ("LANG", "C.utf8")]),
+            IconSet::emoji()
+        );
+    }
+
+    #[test]
+    pub fn test_ascii_sets_are_ascii() {
+        for set in [IconSet::ascii(), IconSet::none()] {
+            for icon in [
+                set.builder_tab,
+                set.birds_eye_tab,
+                set.run_time,
+                set.ellipsis,
+                set.sort_ascending,
+                set.sort_descending,
+                set.health,
+            ] {
+                assert!(icon.is_ascii(), "{icon}");
+            }
+        }
+    }
+
+    #[test]
+    pub fn test_pad_to_width() {
+        assert_eq!(pad_to_width("⏰", 4), "⏰  ");
+        assert_eq!(pad_to_width("time", 4), "time");
+        assert_eq!(pad_to_width("", 2), "  ");
+        assert_eq!(pad_to_width("longer", 2), "longer");
+    }
+}
@@ -1,26 +1,39 @@
-use std::{error::Error, io, io::Stdout, panic};
+use std::{error::Error, time::Duration};
 
 use ratatui::text::Line;
 use strum::{Display, EnumCount, EnumIter, FromRepr};
 
+pub mod cancel;
+pub mod diagnostics;
 pub mod event_loop;
+pub mod export;
+pub mod format;
 pub mod get_stats;
 pub mod gruvbox;
+pub mod icons;
 pub mod listen_to_output;
+pub mod names;
+pub mod nix_cli;
+pub mod proc_poller;
+pub mod proc_table;
+pub mod session;
+pub mod store_path;
 pub mod ui;
+pub mod utilization;
 
-use crossterm::{
-    event::DisableMouseCapture,
-    execute,
-    terminal::{
-        disable_raw_mode, enable_raw_mode, EnterAlternateScreen,
-        LeaveAlternateScreen,
-    },
+use cancel::CancelPlan;
+use diagnostics::{HealthMonitor, HEALTH_INTERVAL};
+use event_loop::{
+    event_loop, parse_interval, CrosstermInput, TerminalGuard, MAX_INTERVAL,
+    MIN_INTERVAL,
 };
-use event_loop::event_loop;
-use ratatui::{
-    backend::CrosstermBackend, style::Style, widgets::ScrollbarState,
+use icons::{pad_to_width, IconSet};
+use proc_poller::{
+    ProcPoller, LOW_POWER_POLL_INTERVAL, MIN_POLL_INTERVAL, PROC_POLL_INTERVAL,
 };
+use proc_table::ProcTable;
+use ratatui::{style::Style, widgets::ScrollbarState};
+use session::{controlling_tty, lock_path, runtime_dir, TtyLock};
 use tui_tree_widget::TreeState;
 use ui::{
     BORDER_STYLE_SELECTED, BORDER_STYLE_UNSELECTED, TITLE_STYLE_SELECTED,
@@ -28,7 +41,6 @@ use ui::{
 };
 
 type Result<T> = std::result::Result<T, Box<dyn Error>>;
-type Terminal = ratatui::Terminal<CrosstermBackend<Stdout>>;
 
 #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum Pane {
@@ -60,8 +72,17 @@ pub enum SelectedTab {
 }
 
 impl SelectedTab {
-    fn title(self) -> Line<'static> {
-        format!("  {self}  ").into()
+    fn title(self, icons: &IconSet) -> Line<'static> {
+        let icon = match self {
+            SelectedTab::BuilderView => icons.builder_tab,
+            SelectedTab::BirdsEyeView => icons.birds_eye_tab,
+        };
+        if icon.is_empty() {
+            format!("  {self}  ").into()
+        } else {
+            // same width whether the icon is an emoji or ascii
+            format!("  {} {self}  ", pad_to_width(icon, 3)).into()
+        }
     }
 
     fn previous(self) -> Self {
@@ -84,6 +105,33 @@ pub struct App {
     builder_view: BuilderViewState,
     birds_eye_view: BirdsEyeViewState,
     tab_selected: SelectedTab,
+    pub icons: IconSet,
+    /// the terminal told us it lost focus. Terminals that don't report
+    /// focus never set this
+    pub unfocused: bool,
+    /// --low-power: behave as if unfocused all the time
+    pub low_power: bool,
+    /// --frame-interval, if given
+    pub frame_interval: Option<Duration>,
+    /// --session-name, shown in the window title
+    pub session_name: Option<String>,
+    /// daemon and store health, for the indicator next to the title. Never
+    /// spawned with --no-probes
+    pub health: HealthMonitor,
+}
+
+impl App {
+    /// nobody is watching closely, so draw rarely
+    pub fn is_idle(&self) -> bool {
+        self.unfocused || self.low_power
+    }
+
+    /// the builder tree and table are on screen, rather than another tab or
+    /// the manual
+    pub fn builder_view_visible(&self) -> bool {
+        self.tab_selected == SelectedTab::BuilderView
+            && !self.builder_view.man_toggle
+    }
 }
 
 #[derive(Default, Debug)]
@@ -99,6 +147,13 @@ pub struct BuilderViewState {
     state: TreeState<String>,
     pub selected_pane: Pane,
     pub man_toggle: bool,
+    pub proc_poller: ProcPoller,
+    /// the process table in the right hand pane
+    pub proc_table: ProcTable,
+    /// waiting for the user to confirm `x`
+    pub pending_cancel: Option<CancelPlan>,
+    /// one line of feedback, e.g. the outcome of a cancel
+    pub status_message: Option<String>,
 }
 
 impl BuilderViewState {
@@ -131,7 +186,51 @@ impl BuilderViewState {
     }
 }
 
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{msg}");
+    std::process::exit(2);
+}
+
 pub fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let has_flag = |flags: &[&str]| args.iter().any(|a| flags.contains(&&**a));
+    if has_flag(&["--version", "-V"]) {
+        std::process::exit(diagnostics::print_version(
+            has_flag(&["--verbose", "-v"]),
+            has_flag(&["--json"]),
+            !has_flag(&["--no-probes"]),
+        ));
+    }
+
+    let flag_value = |flag: &str| {
+        args.iter()
+            .position(|a| a == flag)
+            .map(|i| args.get(i + 1).map(String::as_str).unwrap_or_default())
+    };
+    let icons = match flag_value("--icons") {
+        Some(name) => IconSet::from_name(name).unwrap_or_else(|| {
+            usage_error("--icons expects one of emoji, ascii, none, auto")
+        }),
+        None => IconSet::detect(),
+    };
+    let low_power = has_flag(&["--low-power"]);
+    let parse_interval_flag = |flag: &str, min: Duration| {
+        flag_value(flag).map(|value| {
+            parse_interval(value, min, MAX_INTERVAL)
+                .unwrap_or_else(|e| usage_error(&format!("{flag}: {e}")))
+        })
+    };
+    let poll_interval =
+        parse_interval_flag("--poll-interval", MIN_POLL_INTERVAL).unwrap_or(
+            if low_power {
+                LOW_POWER_POLL_INTERVAL
+            } else {
+                PROC_POLL_INTERVAL
+            },
+        );
+    let frame_interval = parse_interval_flag("--frame-interval", MIN_INTERVAL);
+    let session_name = flag_value("--session-name").map(str::to_string);
+
     if !sysinfo::IS_SUPPORTED_SYSTEM {
         panic!("This OS is supported!");
     }
@@ -157,24 +256,45 @@ pub fn main() {
 
     // construct_everything();
 
-    run().unwrap();
-}
-
-fn run() -> Result<()> {
-    let mut terminal = setup_terminal()?;
+    // held until main returns
+    let _tty_lock = controlling_tty().map(|tty| {
+        let path = lock_path(&runtime_dir(), &tty, session_name.as_deref());
+        TtyLock::acquire(
+            &path,
+            &tty,
+            std::process::id(),
+            has_flag(&["--force"]),
+        )
+        .unwrap_or_else(|e| {
+            eprintln!("{e}");
+            std::process::exit(1);
+        })
+    });
 
-    // create app and run it
-    let app = App::default();
-    let res = event_loop(&mut terminal, app);
+    run(App {
+        builder_view: BuilderViewState {
+            proc_poller: ProcPoller::spawn(poll_interval),
+            ..Default::default()
+        },
+        icons,
+        low_power,
+        frame_interval,
+        session_name,
+        health: if has_flag(&["--no-probes"]) {
+            HealthMonitor::default()
+        } else {
+            HealthMonitor::spawn(HEALTH_INTERVAL)
+        },
+        ..Default::default()
+    })
+    .unwrap();
+}
 
-    // restore terminal
-    disable_raw_mode()?;
-    execute!(
-        terminal.backend_mut(),
-        LeaveAlternateScreen,
-        DisableMouseCapture
-    )?;
-    terminal.show_cursor()?;
+fn run(app: App) -> Result<()> {
+    let mut guard = TerminalGuard::new(app.session_name.as_deref())?;
+    let res = event_loop(guard.terminal(), app, &mut CrosstermInput);
+    // put the terminal back before printing anything
+    drop(guard);
 
     if let Err(err) = res {
         println!("{err:?}");
@@ -182,23 +302,3 @@ fn run() -> Result<()> {
 
     Ok(())
 }
-
-fn setup_terminal() -> Result<Terminal> {
-    enable_raw_mode()?;
-    let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = ratatui::Terminal::new(backend)?;
-    terminal.hide_cursor()?;
-
-    let panic_hook = panic::take_hook();
-    panic::set_hook(Box::new(move |panic| {
-        let _ = disable_raw_mode();
-        let _ =
-            execute!(io::stderr(), LeaveAlternateScreen, DisableMouseCapture);
-
-        panic_hook(panic);
-    }));
-
-    Ok(terminal)
-}
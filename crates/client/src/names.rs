@@ -0,0 +1,253 @@
+// nom style shortening of derivation names for display. Only ever applied
+// when rendering: anything that sorts, filters or talks to nix keeps using
+// the full name
+use crate::store_path::{StorePath, DEFAULT_STORE_DIR};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameRules {
+    /// package sets that prefix every name they build with the interpreter
+    /// and its version, e.g. "python3.11-" or "perl5.38.2-"
+    pub language_prefixes: Vec<String>,
+    /// the few of those whose prefix has no version ("emacs-magit"). For
+    /// the rest a bare "lua-" is just part of the name, as in
+    /// lua-language-server
+    pub unversioned_prefixes: Vec<String>,
+    /// (suffix, tag): suffixes nixpkgs tacks onto helper derivations, shown
+    /// as a short tag instead
+    pub suffixes: Vec<(String, String)>,
+}
+
+impl Default for NameRules {
+    fn default() -> Self {
+        NameRules {
+            language_prefixes: [
+                "python",
+                "perl",
+                "ruby",
+                "lua",
+                "php",
+                "ocaml",
+                "emacs",
+                "vimplugin",
+                "node",
+            ]
+            .map(String::from)
+            .to_vec(),
+            unversioned_prefixes: ["php", "emacs", "vimplugin"]
+                .map(String::from)
+                .to_vec(),
+            suffixes: [
+                ("-python-imports-check", "imports check"),
+                ("-source", "src"),
+                ("-env", "env"),
+                ("-wrapped", "wrapped"),
+                ("-hook", "hook"),
+            ]
+            .map(|(suffix, tag)| (suffix.to_string(), tag.to_string()))
+            .to_vec(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShortName {
+    /// e.g. "python3.11"
+    pub language: Option<String>,
+    pub name: String,
+    /// e.g. "src"
+    pub tag: Option<String>,
+}
+
+impl std::fmt::Display for ShortName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(language) = &self.language {
+            write!(f, "[{language}] ")?;
+        }
+        f.write_str(&self.name)?;
+        if let Some(tag) = &self.tag {
+            write!(f, " ({tag})")?;
+        }
+        Ok(())
+    }
+}
+
+impl NameRules {
+    /// splits "python3.11-foo-1.0" into ("python3.11", "foo-1.0"). The
+    /// interpreter itself ("python3-3.11.9") is left alone: what follows the
+    /// prefix has to start with a letter
+    fn split_language<'a>(&self, name: &'a str) -> Option<(&'a str, &'a str)> {
+        self.language_prefixes.iter().find_map(|stem| {
+            let rest = name.strip_prefix(stem.as_str())?;
+            let version_len = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            if version_len == 0 && !self.unversioned_prefixes.contains(stem) {
+                return None;
+            }
+            let package = rest[version_len..].strip_prefix('-')?;
+            package
+                .starts_with(|c: char| c.is_alphabetic())
+                .then(|| (&name[..stem.len() + version_len], package))
+        })
+    }
+
+    pub fn shorten(&self, name: &str) -> ShortName {
+        let (language, rest) = match self.split_language(name) {
+            Some((language, rest)) => (Some(language.to_string()), rest),
+            None => (None, name),
+        };
+        let (rest, tag) = self
+            .suffixes
+            .iter()
+            .find_map(|(suffix, tag)| {
+                rest.strip_suffix(suffix.as_str())
+                    .filter(|rest| !rest.is_empty())
+                    .map(|rest| (rest, Some(tag.clone())))
+            })
+            .unwrap_or((rest, None));
+        ShortName {
+            language,
+            name: rest.to_string(),
+            tag,
+        }
+    }
+}
+
+/// drops the store dir and hash from every store path in a command line
+/// argument: "/nix/store/<hash>-bash-5.2p26/bin/bash" ->
+/// "bash-5.2p26/bin/bash". Unlike `NameRules::shorten` this adds no spaces,
+/// so the arguments still join into a readable command line. Anything that
+/// doesn't parse as a store path is left as is
+pub fn shorten_store_paths(arg: &str) -> String {
+    let prefix = format!("{DEFAULT_STORE_DIR}/");
+    let mut out = String::with_capacity(arg.len());
+    let mut rest = arg;
+    while let Some(start) = rest.find(&prefix) {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + prefix.len()..];
+        let end = after
+            .find(|c: char| c == '/' || c.is_whitespace() || c == ':')
+            .unwrap_or(after.len());
+        match StorePath::from_base_name(&after[..end]) {
+            Ok(path) => out.push_str(path.name.as_str()),
+            Err(_) => out.push_str(&rest[start..start + prefix.len() + end]),
+        }
+        rest = &after[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// real nixpkgs names and how they should read
+    const CORPUS: [(&str, &str); 49] = [
+        (
+            "python3.11-sphinxcontrib-applehelp-1.0.8-python-imports-check",
+            "[python3.11] sphinxcontrib-applehelp-1.0.8 (imports check)",
+        ),
+        ("python3.11-numpy-1.26.4", "[python3.11] numpy-1.26.4"),
+        ("python3.12-requests-2.31.0", "[python3.12] requests-2.31.0"),
+        (
+            "python3.11-setuptools-69.1.1",
+            "[python3.11] setuptools-69.1.1",
+        ),
+        (
+            "python3.11-pytest-check-hook",
+            "[python3.11] pytest-check (hook)",
+        ),
+        ("python3-3.11.9", "python3-3.11.9"),
+        ("python3.11-3.11.9-env", "python3.11-3.11.9 (env)"),
+        ("perl5.38.2-JSON-4.10", "[perl5.38.2] JSON-4.10"),
+        (
+            "perl5.38.2-Module-Build-0.4234",
+            "[perl5.38.2] Module-Build-0.4234",
+        ),
+        ("perl-5.38.2", "perl-5.38.2"),
+        ("ruby3.1-nokogiri-1.16.0", "[ruby3.1] nokogiri-1.16.0"),
+        ("ruby-3.1.4", "ruby-3.1.4"),
+        ("lua5.1-lpeg-1.1.0-1", "[lua5.1] lpeg-1.1.0-1"),
+        ("lua-5.4.6", "lua-5.4.6"),
+        ("lua-language-server-3.9.1", "lua-language-server-3.9.1"),
+        ("php-8.2.17", "php-8.2.17"),
+        ("php-redis-6.0.2", "[php] redis-6.0.2"),
+        ("ocaml5.1.1-dune-3.15.0", "[ocaml5.1.1] dune-3.15.0"),
+        ("ocaml-5.1.1", "ocaml-5.1.1"),
+        ("emacs-magit-20240426.2118", "[emacs] magit-20240426.2118"),
+        ("emacs-29.3", "emacs-29.3"),
+        (
+            "vimplugin-nvim-treesitter-2024-05-01",
+            "[vimplugin] nvim-treesitter-2024-05-01",
+        ),
+        (
+            "vimplugin-treesitter-grammar-hoon",
+            "[vimplugin] treesitter-grammar-hoon",
+        ),
+        ("nodejs-20.12.2", "nodejs-20.12.2"),
+        ("node-gyp-build-4.8.0", "node-gyp-build-4.8.0"),
+        ("helix-24.03", "helix-24.03"),
+        ("helix-24.03-source", "helix-24.03 (src)"),
+        ("source", "source"),
+        ("linux-6.6.30", "linux-6.6.30"),
+        ("linux-6.6.30-modules-shrunk", "linux-6.6.30-modules-shrunk"),
+        ("firefox-125.0.3", "firefox-125.0.3"),
+        ("firefox-unwrapped-125.0.3", "firefox-unwrapped-125.0.3"),
+        ("ghc-9.6.5", "ghc-9.6.5"),
+        ("ghc-9.6.5-with-packages", "ghc-9.6.5-with-packages"),
+        ("text-2.0.2", "text-2.0.2"),
+        ("openssl-3.0.13", "openssl-3.0.13"),
+        ("cargo-1.78.0", "cargo-1.78.0"),
+        ("rustc-1.78.0", "rustc-1.78.0"),
+        ("rustc-wrapper-1.78.0", "rustc-wrapper-1.78.0"),
+        ("clang-wrapper-17.0.6", "clang-wrapper-17.0.6"),
+        ("gcc-13.2.0", "gcc-13.2.0"),
+        ("binutils-2.41", "binutils-2.41"),
+        ("bash-5.2p26", "bash-5.2p26"),
+        ("stdenv-linux", "stdenv-linux"),
+        ("hook", "hook"),
+        ("make-shell-wrapper-hook", "make-shell-wrapper (hook)"),
+        (
+            "nixos-system-nixos-24.05.20240508",
+            "nixos-system-nixos-24.05.20240508",
+        ),
+        ("home-manager-generation", "home-manager-generation"),
+        ("user-environment", "user-environment"),
+    ];
+
+    #[test]
+    pub fn test_corpus() {
+        let rules = NameRules::default();
+        for (name, expected) in CORPUS {
+            assert_eq!(rules.shorten(name).to_string(), expected, "{name}");
+        }
+    }
+
+    #[test]
+    pub fn test_shorten_store_paths() {
+        assert_eq!(
+            shorten_store_paths(
+                "/nix/store/z4ps207hnvyh0lsrlmgkqyyfj3bbf37l-bash-5.2p26/\
+                 bin/bash"
+            ),
+            "bash-5.2p26/bin/bash"
+        );
+        // no language or tag, which would put spaces inside one argument
+        assert_eq!(
+            shorten_store_paths(
+                "PATH=/nix/store/z4ps207hnvyh0lsrlmgkqyyfj3bbf37l-python3.11-\
+                 numpy-1.26.4/bin:/nix/store/not-a-store-path/bin"
+            ),
+            "PATH=python3.11-numpy-1.26.4/bin:/nix/store/not-a-store-path/bin"
+        );
+        assert_eq!(
+            shorten_store_paths(
+                "/nix/store/z4ps207hnvyh0lsrlmgkqyyfj3bbf37l-hello-2.12-source"
+            ),
+            "hello-2.12-source"
+        );
+        assert_eq!(shorten_store_paths("-e"), "-e");
+        assert_eq!(shorten_store_paths("/nix/store/"), "/nix/store/");
+    }
+}
@@ -0,0 +1,527 @@
+// every `nix` invocation goes through here. Under load nix fails for reasons
+// that go away on their own ("database is locked", the daemon socket
+// refusing connections), and treating those like real errors left us
+// without data for the rest of the session
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    process::{Command, Output, Stdio},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use lazy_static::lazy_static;
+use rand::Rng;
+
+lazy_static! {
+    /// retries per nix subcommand, for diagnostics
+    static ref RETRY_COUNTS: Mutex<BTreeMap<String, u64>> = Mutex::default();
+    /// invocations that failed permanently, and when, so we don't keep
+    /// asking
+    static ref PERMANENT_FAILURES: Mutex<HashMap<Vec<String>, Failure>> =
+        Mutex::default();
+    /// invocations currently running, so identical concurrent calls share
+    /// one run
+    static ref IN_FLIGHT: Mutex<HashMap<Vec<String>, Arc<Shared>>> =
+        Mutex::default();
+}
+
+/// how long a permanent failure is answered from the cache. Config gets
+/// fixed and daemons get restarted, so "permanent" only means "not worth
+/// retrying right now"
+pub const PERMANENT_FAILURE_TTL: Duration = Duration::from_secs(60);
+
+/// when it failed, and its stderr
+type Failure = (Instant, String);
+
+/// the result of one run, for the callers waiting on it
+type Shared = (Mutex<Option<Result<Output, NixError>>>, Condvar);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// including the first try
+    pub attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+/// three tries over at most ~10s
+pub const DEFAULT_RETRY_POLICY: RetryPolicy = RetryPolicy {
+    attempts: 3,
+    base_delay: Duration::from_secs(2),
+    max_delay: Duration::from_secs(8),
+};
+
+#[derive(Debug)]
+pub enum NixError {
+    /// nix couldn't be started at all, e.g. it isn't on the PATH
+    Spawn(io::Error),
+    /// still failing after every retry
+    Transient(String),
+    Permanent(String),
+    /// killed after not finishing in time
+    TimedOut(Duration),
+}
+
+impl std::fmt::Display for NixError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NixError::Spawn(e) => write!(f, "could not run nix: {e}"),
+            NixError::Transient(stderr) => {
+                write!(f, "nix kept failing: {stderr}")
+            }
+            NixError::Permanent(stderr) => write!(f, "nix failed: {stderr}"),
+            NixError::TimedOut(timeout) => {
+                write!(f, "nix did not answer within {}ms", timeout.as_millis())
+            }
+        }
+    }
+}
+
+impl std::error::Error for NixError {}
+
+impl NixError {
+    /// io::Error isn't Clone, so a shared spawn error is rebuilt from its
+    /// kind and message
+    fn duplicate(&self) -> Self {
+        match self {
+            NixError::Spawn(e) => {
+                NixError::Spawn(io::Error::new(e.kind(), e.to_string()))
+            }
+            NixError::Transient(stderr) => NixError::Transient(stderr.clone()),
+            NixError::Permanent(stderr) => NixError::Permanent(stderr.clone()),
+            NixError::TimedOut(timeout) => NixError::TimedOut(*timeout),
+        }
+    }
+}
+
+const TRANSIENT_ERRORS: [&str; 7] = [
+    "database is locked",
+    "database is busy",
+    "waiting for lock",
+    "resource temporarily unavailable",
+    "cannot connect to socket",
+    "connection refused",
+    "timed out",
+];
+
+/// whether a failed invocation is worth retrying. Anything we don't
+/// recognize (evaluation errors, missing paths, unknown commands) is
+/// permanent
+pub fn is_transient(output: &Output) -> bool {
+    // killed by a signal
+    if output.status.code().is_none() {
+        return true;
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).to_lowercase();
+    TRANSIENT_ERRORS.iter().any(|e| stderr.contains(e))
+}
+
+/// delay before retry number `retry` (starting at 0): exponential, capped,
+/// plus up to 50% jitter so callers that failed together don't retry
+/// together
+pub fn backoff(policy: &RetryPolicy, retry: u32) -> Duration {
+    let delay = policy
+        .base_delay
+        .saturating_mul(2u32.saturating_pow(retry))
+        .min(policy.max_delay);
+    let jitter = rand::thread_rng().gen_range(0.0..=0.5);
+    delay + delay.mul_f64(jitter)
+}
+
+/// runs `args` through `run`, retrying transient failures per `policy` and
+/// sleeping with `sleep` in between. Split out from `nix` so tests can
+/// script the failures and skip the sleeps
+pub fn run_with_retry(
+    policy: &RetryPolicy,
+    args: &[&str],
+    mut run: impl FnMut(&[&str]) -> io::Result<Output>,
+    mut sleep: impl FnMut(Duration),
+) -> Result<Output, NixError> {
+    let mut retry = 0;
+    loop {
+        let output = run(args).map_err(NixError::Spawn)?;
+        if output.status.success() {
+            return Ok(output);
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+        if !is_transient(&output) {
+            return Err(NixError::Permanent(stderr));
+        }
+        if retry + 1 >= policy.attempts {
+            return Err(NixError::Transient(stderr));
+        }
+        if let Some(subcommand) = args.first() {
+            *RETRY_COUNTS
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(subcommand.to_string())
+                .or_default() += 1;
+        }
+        sleep(backoff(policy, retry));
+        retry += 1;
+    }
+}
+
+/// runs `nix args...`, retrying transient failures. Permanent failures are
+/// remembered for `PERMANENT_FAILURE_TTL` and returned straight away
+pub fn nix(args: &[&str]) -> Result<Output, NixError> {
+    run_cached(args, true, run_nix)
+}
+
+/// like `nix`, but runs nix even if the same call failed permanently a
+/// moment ago. For when the user explicitly asked to look again
+pub fn nix_fresh(args: &[&str]) -> Result<Output, NixError> {
+    run_cached(args, false, run_nix)
+}
+
+fn run_nix(args: &[&str]) -> Result<Output, NixError> {
+    run_with_retry(
+        &DEFAULT_RETRY_POLICY,
+        args,
+        |args| Command::new("nix").args(args).output(),
+        thread::sleep,
+    )
+}
+
+fn cached_failure(key: &[String], now: Instant) -> Option<NixError> {
+    let mut failures =
+        PERMANENT_FAILURES.lock().unwrap_or_else(|e| e.into_inner());
+    let (at, stderr) = failures.get(key)?;
+    if now.duration_since(*at) < PERMANENT_FAILURE_TTL {
+        return Some(NixError::Permanent(stderr.clone()));
+    }
+    failures.remove(key);
+    None
+}
+
+/// `run` behind the permanent failure cache (consulted only if
+/// `use_cache`, but always updated) and the in flight table: a call
+/// identical to one already running waits for that one's result instead of
+/// starting nix again
+fn run_cached(
+    args: &[&str],
+    use_cache: bool,
+    run: impl FnOnce(&[&str]) -> Result<Output, NixError>,
+) -> Result<Output, NixError> {
+    let key: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    if use_cache {
+        if let Some(e) = cached_failure(&key, Instant::now()) {
+            return Err(e);
+        }
+    }
+    let (shared, leader) = {
+        let mut in_flight = IN_FLIGHT.lock().unwrap_or_else(|e| e.into_inner());
+        match in_flight.get(&key) {
+            Some(shared) => (shared.clone(), false),
+            None => {
+                let shared = Arc::new(Shared::default());
+                in_flight.insert(key.clone(), shared.clone());
+                (shared, true)
+            }
+        }
+    };
+    let (slot, done) = &*shared;
+    if !leader {
+        let result = done
+            .wait_while(slot.lock().unwrap_or_else(|e| e.into_inner()), |r| {
+                r.is_none()
+            })
+            .unwrap_or_else(|e| e.into_inner());
+        return match result.as_ref() {
+            Some(Ok(output)) => Ok(output.clone()),
+            Some(Err(e)) => Err(e.duplicate()),
+            None => unreachable!("waited until the result was set"),
+        };
+    }
+
+    let result = run(args);
+    {
+        let mut failures =
+            PERMANENT_FAILURES.lock().unwrap_or_else(|e| e.into_inner());
+        match &result {
+            Err(NixError::Permanent(stderr)) => {
+                failures.insert(key.clone(), (Instant::now(), stderr.clone()));
+            }
+            _ => {
+                failures.remove(&key);
+            }
+        }
+    }
+    IN_FLIGHT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&key);
+    *slot.lock().unwrap_or_else(|e| e.into_inner()) = Some(match &result {
+        Ok(output) => Ok(output.clone()),
+        Err(e) => Err(e.duplicate()),
+    });
+    done.notify_all();
+    result
+}
+
+/// how often `run_with_deadline` checks whether the child has exited
+const DEADLINE_POLL: Duration = Duration::from_millis(10);
+
+/// runs `nix args...` once, killing it if it hasn't finished within
+/// `timeout`. Neither retried nor answered from the failure cache, for
+/// probes that want to know how nix is doing right now
+pub fn nix_with_timeout(
+    args: &[&str],
+    timeout: Duration,
+) -> Result<Output, NixError> {
+    let mut command = Command::new("nix");
+    command.args(args);
+    run_with_deadline(command, timeout)
+}
+
+/// output is only read once the child has exited, so this is for commands
+/// that print less than a pipe buffer
+pub fn run_with_deadline(
+    mut command: Command,
+    timeout: Duration,
+) -> Result<Output, NixError> {
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(NixError::Spawn)?;
+    let deadline = Instant::now() + timeout;
+    while child.try_wait().map_err(NixError::Spawn)?.is_none() {
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(NixError::TimedOut(timeout));
+        }
+        thread::sleep(DEADLINE_POLL);
+    }
+    let output = child.wait_with_output().map_err(NixError::Spawn)?;
+    if output.status.success() {
+        return Ok(output);
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    if is_transient(&output) {
+        Err(NixError::Transient(stderr))
+    } else {
+        Err(NixError::Permanent(stderr))
+    }
+}
+
+/// (nix subcommand, number of retries) so far
+pub fn retry_counts() -> Vec<(String, u64)> {
+    RETRY_COUNTS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(k, v)| (k.clone(), *v))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::VecDeque, os::unix::process::ExitStatusExt,
+        process::ExitStatus,
+    };
+
+    use super::*;
+
+    const POLICY: RetryPolicy = RetryPolicy {
+        attempts: 3,
+        base_delay: Duration::from_millis(100),
+        max_delay: Duration::from_millis(150),
+    };
+
+    fn output(code: i32, stderr: &str) -> io::Result<Output> {
+        Ok(Output {
+            status: ExitStatus::from_raw(code << 8),
+            stdout: b"ok".to_vec(),
+            stderr: stderr.as_bytes().to_vec(),
+        })
+    }
+
+    /// replays `outputs` and records the delays it was asked to sleep for
+    fn scripted(
+        outputs: Vec<io::Result<Output>>,
+    ) -> (Result<Output, NixError>, usize, Vec<Duration>) {
+        let mut outputs = VecDeque::from(outputs);
+        let mut calls = 0;
+        let mut sleeps = vec![];
+        let result = run_with_retry(
+            &POLICY,
+            &["path-info", "/nix/store/x"],
+            |_| {
+                calls += 1;
+                outputs.pop_front().unwrap()
+            },
+            |delay| sleeps.push(delay),
+        );
+        (result, calls, sleeps)
+    }
+
+    const LOCKED: &str = "error: SQLite database '/nix/var/nix/db/db.sqlite' \
+                          is busy: database is locked";
+
+    #[test]
+    pub fn test_transient_failures_are_retried() {
+        let (result, calls, sleeps) =
+            scripted(vec![output(1, LOCKED), output(1, LOCKED), output(0, "")]);
+        assert_eq!(result.unwrap().stdout, b"ok");
+        assert_eq!(calls, 3);
+        // exponential, capped at max_delay, plus at most 50% jitter
+        assert_eq!(sleeps.len(), 2);
+        assert!(sleeps[0] >= Duration::from_millis(100));
+        assert!(sleeps[0] <= Duration::from_millis(150));
+        assert!(sleeps[1] >= Duration::from_millis(150));
+        assert!(sleeps[1] <= Duration::from_millis(225));
+        assert!(retry_counts()
+            .iter()
+            .any(|(cmd, n)| cmd == "path-info" && *n >= 2));
+    }
+
+    #[test]
+    pub fn test_retries_are_bounded() {
+        let (result, calls, sleeps) = scripted(vec![
+            output(1, LOCKED),
+            output(1, LOCKED),
+            output(1, LOCKED),
+        ]);
+        assert!(matches!(result, Err(NixError::Transient(_))));
+        assert_eq!(calls, POLICY.attempts as usize);
+        assert_eq!(sleeps.len(), 2);
+    }
+
+    #[test]
+    pub fn test_permanent_failures_are_not_retried() {
+        let (result, calls, sleeps) = scripted(vec![output(
+            1,
+            "error: path '/nix/store/x' is not valid",
+        )]);
+        assert!(matches!(result, Err(NixError::Permanent(_))));
+        assert_eq!(calls, 1);
+        assert!(sleeps.is_empty());
+
+        let (result, calls, _) =
+            scripted(vec![Err(io::Error::new(io::ErrorKind::NotFound, "nix"))]);
+        assert!(matches!(result, Err(NixError::Spawn(_))));
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    pub fn test_permanent_failures_expire() {
+        let key = vec!["test-expire".to_string()];
+        let now = Instant::now();
+        let fresh = (now, "error: nope".to_string());
+        PERMANENT_FAILURES
+            .lock()
+            .unwrap()
+            .insert(key.clone(), fresh);
+        assert!(matches!(
+            cached_failure(&key, now),
+            Some(NixError::Permanent(_))
+        ));
+        assert!(cached_failure(&key, now + PERMANENT_FAILURE_TTL).is_none());
+        // and the stale entry is gone
+        assert!(!PERMANENT_FAILURES.lock().unwrap().contains_key(&key));
+    }
+
+    #[test]
+    pub fn test_uncached_calls_run_again() {
+        let args = ["test-uncached"];
+        let calls = std::cell::Cell::new(0);
+        let fail = |_: &[&str]| {
+            calls.set(calls.get() + 1);
+            Err(NixError::Permanent("error: nope".to_string()))
+        };
+        assert!(run_cached(&args, true, fail).is_err());
+        assert!(run_cached(&args, true, fail).is_err());
+        assert_eq!(calls.get(), 1);
+        assert!(run_cached(&args, false, fail).is_err());
+        assert_eq!(calls.get(), 2);
+
+        // a success clears the cached failure
+        assert!(run_cached(&args, false, |_| output(0, "")
+            .map_err(NixError::Spawn))
+        .is_ok());
+        let mut ran = false;
+        let _ = run_cached(&args, true, |_| {
+            ran = true;
+            output(0, "").map_err(NixError::Spawn)
+        });
+        assert!(ran);
+    }
+
+    #[test]
+    pub fn test_concurrent_calls_share_a_run() {
+        let args = ["test-concurrent"];
+        let key = vec!["test-concurrent".to_string()];
+        let (release, released) = std::sync::mpsc::channel::<()>();
+        let leader = thread::spawn(move || {
+            run_cached(&args, true, |_| {
+                released.recv().unwrap();
+                output(0, "").map_err(NixError::Spawn)
+            })
+        });
+        let waiters = || {
+            IN_FLIGHT
+                .lock()
+                .unwrap()
+                .get(&key)
+                .map_or(0, Arc::strong_count)
+        };
+        // the table's and the leader's reference
+        while waiters() < 2 {
+            thread::yield_now();
+        }
+        let follower = thread::spawn(move || {
+            run_cached(&args, true, |_| panic!("should share the leader's run"))
+        });
+        while waiters() < 3 {
+            thread::yield_now();
+        }
+        release.send(()).unwrap();
+        assert_eq!(leader.join().unwrap().unwrap().stdout, b"ok");
+        assert_eq!(follower.join().unwrap().unwrap().stdout, b"ok");
+        assert!(!IN_FLIGHT.lock().unwrap().contains_key(&key));
+    }
+
+    #[test]
+    pub fn test_deadline_kills_hung_commands() {
+        let mut hung = Command::new("sleep");
+        hung.arg("10");
+        let start = Instant::now();
+        assert!(matches!(
+            run_with_deadline(hung, Duration::from_millis(100)),
+            Err(NixError::TimedOut(_))
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let mut quick = Command::new("sh");
+        quick.args(["-c", "echo ok"]);
+        let output = run_with_deadline(quick, Duration::from_secs(5)).unwrap();
+        assert_eq!(output.stdout, b"ok\n");
+
+        let mut failing = Command::new("sh");
+        failing.args(["-c", "echo 'error: nope' >&2; exit 1"]);
+        assert!(matches!(
+            run_with_deadline(failing, Duration::from_secs(5)),
+            Err(NixError::Permanent(stderr)) if stderr == "error: nope"
+        ));
+    }
+
+    #[test]
+    pub fn test_killed_by_signal_is_transient() {
+        let killed = Output {
+            // SIGKILL
+            status: ExitStatus::from_raw(9),
+            stdout: vec![],
+            stderr: vec![],
+        };
+        assert!(is_transient(&killed));
+        assert!(!is_transient(
+            &output(1, "error: undefined variable").unwrap()
+        ));
+    }
+}
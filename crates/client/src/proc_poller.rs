@@ -0,0 +1,297 @@
+// Sampling every process on the machine (`System::new_all`) and regenerating
+// the builder tree takes far longer than a frame once there are a lot of
+// builders, so it lives on its own thread. The draw call only ever grabs the
+// most recently published snapshot, and renders the previous one until the
+// next sample is ready.
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use sysinfo::System;
+use tui_tree_widget::TreeItem;
+
+use crate::{
+    get_stats::{
+        gen_ui_by_nix_builder, get_active_users_and_pids, ProcMetadata,
+    },
+    utilization::{
+        count_active_builders, read_nix_build_config, NixBuildConfig,
+        UtilizationHistory,
+    },
+};
+
+pub const PROC_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// sampling every process more often than this is all overhead
+pub const MIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// with --low-power, sample this often instead
+pub const LOW_POWER_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Default, Debug)]
+pub struct BuilderSnapshot {
+    pub user_map: HashMap<String, BTreeSet<ProcMetadata>>,
+    pub items: Vec<TreeItem<'static, String>>,
+    pub utilization: UtilizationHistory,
+    pub nix_config: NixBuildConfig,
+    pub load_average: f64,
+}
+
+impl BuilderSnapshot {
+    pub fn new(user_map: HashMap<String, BTreeSet<ProcMetadata>>) -> Self {
+        let items = gen_ui_by_nix_builder(&user_map);
+        BuilderSnapshot {
+            user_map,
+            items,
+            ..Default::default()
+        }
+    }
+}
+
+/// what the sampling thread should do between samples. Lives behind a mutex
+/// (rather than being captured when the thread starts) so changes take
+/// effect on the very next wait
+#[derive(Debug)]
+struct Control {
+    interval: Duration,
+    /// set while nobody is looking (e.g. the terminal lost focus). No samples
+    /// are taken while paused, so the utilization history skips the gap
+    /// rather than recording a dip
+    paused: bool,
+    /// sample right away instead of waiting out the interval
+    refresh_requested: bool,
+    /// re-read max-jobs and cores before the next sample. Only the user's
+    /// refresh asks for this, resuming doesn't
+    reload_config: bool,
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        Control {
+            interval: PROC_POLL_INTERVAL,
+            paused: false,
+            refresh_requested: false,
+            reload_config: false,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone)]
+pub struct ProcPoller {
+    latest: Arc<Mutex<Arc<BuilderSnapshot>>>,
+    updating: Arc<AtomicBool>,
+    control: Arc<(Mutex<Control>, Condvar)>,
+}
+
+impl ProcPoller {
+    /// spawns the sampling thread. There is only ever one sample in flight,
+    /// so bursts of process churn coalesce into whatever the next sample sees
+    pub fn spawn(interval: Duration) -> Self {
+        let poller = ProcPoller::default();
+        poller.set_interval(interval);
+        let handle = poller.clone();
+        thread::Builder::new()
+            .name("proc-poller".to_string())
+            .spawn(move || {
+                let mut nix_config = read_nix_build_config();
+                let mut utilization = UtilizationHistory::default();
+                loop {
+                    handle.refresh(nix_config, &mut utilization);
+                    if handle.wait() {
+                        nix_config = read_nix_build_config();
+                    }
+                }
+            })
+            .expect("Failed to spawn proc poller thread");
+        poller
+    }
+
+    fn control(&self) -> MutexGuard<'_, Control> {
+        self.control.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// changes to the control are announced here so `wait` can re-check
+    fn notify(&self) {
+        self.control.1.notify_all();
+    }
+
+    /// blocks until the next sample is due: the interval has passed since the
+    /// wait started (re-read on every wake up, so it can change mid wait),
+    /// or a refresh was requested. Never returns while paused. Returns whether
+    /// the nix config should be re-read first
+    fn wait(&self) -> bool {
+        let start = Instant::now();
+        let mut control = self.control();
+        while !control.refresh_requested {
+            if control.paused {
+                control = self
+                    .control
+                    .1
+                    .wait(control)
+                    .unwrap_or_else(|e| e.into_inner());
+                continue;
+            }
+            let remaining = control.interval.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                break;
+            }
+            control = self
+                .control
+                .1
+                .wait_timeout(control, remaining)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+        control.refresh_requested = false;
+        std::mem::take(&mut control.reload_config)
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.control().interval
+    }
+
+    pub fn set_interval(&self, interval: Duration) {
+        self.control().interval = interval;
+        self.notify();
+    }
+
+    /// take a sample now, regardless of the interval, and pick up any
+    /// change to max-jobs or cores
+    pub fn request_refresh(&self) {
+        let mut control = self.control();
+        control.refresh_requested = true;
+        control.reload_config = true;
+        drop(control);
+        self.notify();
+    }
+
+    /// resuming also requests a refresh so the view is fresh right away
+    pub fn set_paused(&self, paused: bool) {
+        let mut control = self.control();
+        if control.paused != paused {
+            control.paused = paused;
+            control.refresh_requested |= !paused;
+            drop(control);
+            self.notify();
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control().paused
+    }
+
+    pub fn refresh(
+        &self,
+        nix_config: NixBuildConfig,
+        utilization: &mut UtilizationHistory,
+    ) {
+        self.updating.store(true, Ordering::Relaxed);
+        let user_map = get_active_users_and_pids();
+        utilization.push(count_active_builders(&user_map));
+        self.publish(BuilderSnapshot {
+            utilization: utilization.clone(),
+            nix_config,
+            load_average: System::load_average().one,
+            ..BuilderSnapshot::new(user_map)
+        });
+    }
+
+    pub fn publish(&self, snapshot: BuilderSnapshot) {
+        // only hold the lock long enough to swap the pointer
+        let snapshot = Arc::new(snapshot);
+        *self.latest.lock().unwrap_or_else(|e| e.into_inner()) = snapshot;
+        self.updating.store(false, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> Arc<BuilderSnapshot> {
+        self.latest
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    pub fn is_updating(&self) -> bool {
+        self.updating.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::mpsc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::ProcPoller;
+
+    /// runs `wait` on another thread and reports how long it took
+    fn timed_wait(poller: &ProcPoller) -> mpsc::Receiver<Duration> {
+        let (tx, rx) = mpsc::channel();
+        let poller = poller.clone();
+        thread::spawn(move || {
+            let start = Instant::now();
+            poller.wait();
+            let _ = tx.send(start.elapsed());
+        });
+        rx
+    }
+
+    const SETTLE: Duration = Duration::from_millis(50);
+    const LONG: Duration = Duration::from_secs(60);
+
+    #[test]
+    pub fn test_wait_honors_interval_changes() {
+        let poller = ProcPoller::default();
+        poller.set_interval(Duration::from_millis(10));
+        let elapsed = timed_wait(&poller).recv_timeout(LONG).unwrap();
+        assert!(elapsed >= Duration::from_millis(10));
+
+        // shortening the interval mid wait re-arms it
+        poller.set_interval(LONG);
+        let done = timed_wait(&poller);
+        assert!(done.recv_timeout(SETTLE).is_err());
+        poller.set_interval(Duration::from_millis(1));
+        assert!(done.recv_timeout(LONG).unwrap() < LONG);
+    }
+
+    #[test]
+    pub fn test_refresh_and_pause_cut_waits_short() {
+        let poller = ProcPoller::default();
+        poller.set_interval(LONG);
+
+        let done = timed_wait(&poller);
+        assert!(done.recv_timeout(SETTLE).is_err());
+        poller.request_refresh();
+        assert!(done.recv_timeout(LONG).unwrap() < LONG);
+
+        // paused: even a zero interval doesn't wake it, resuming does
+        poller.set_interval(Duration::ZERO);
+        poller.set_paused(true);
+        let done = timed_wait(&poller);
+        assert!(done.recv_timeout(SETTLE).is_err());
+        poller.set_paused(false);
+        assert!(done.recv_timeout(LONG).is_ok());
+        assert!(!poller.is_paused());
+    }
+
+    #[test]
+    pub fn test_only_refresh_reloads_config() {
+        let poller = ProcPoller::default();
+        poller.set_interval(Duration::ZERO);
+        assert!(!poller.wait());
+
+        poller.request_refresh();
+        assert!(poller.wait());
+        // once per request
+        assert!(!poller.wait());
+
+        poller.set_paused(true);
+        poller.set_paused(false);
+        assert!(!poller.wait());
+    }
+}
@@ -0,0 +1,246 @@
+// state of the per builder process table (the right hand pane of the builder
+// view) that has to survive between frames
+use std::{cmp::Ordering, collections::BTreeSet};
+
+use ratatui::widgets::TableState;
+use sysinfo::Pid;
+
+use crate::get_stats::ProcMetadata;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProcColumn {
+    #[default]
+    Pid,
+    Env,
+    Parent,
+    PMem,
+    VMem,
+    RunTime,
+    Cmd,
+}
+
+impl ProcColumn {
+    pub const ALL: [ProcColumn; 7] = [
+        ProcColumn::Pid,
+        ProcColumn::Env,
+        ProcColumn::Parent,
+        ProcColumn::PMem,
+        ProcColumn::VMem,
+        ProcColumn::RunTime,
+        ProcColumn::Cmd,
+    ];
+
+    /// the column bound to number key `key`, '1' being the leftmost
+    pub fn from_key(key: char) -> Option<Self> {
+        let idx = key.to_digit(10)?.checked_sub(1)?;
+        Self::ALL.get(idx as usize).copied()
+    }
+
+    /// compares on the raw values, so memory and run time sort numerically
+    /// rather than by their formatted strings
+    fn cmp(self, a: &ProcMetadata, b: &ProcMetadata) -> Ordering {
+        match self {
+            ProcColumn::Pid => a.id.cmp(&b.id),
+            ProcColumn::Env => a.env.cmp(&b.env),
+            ProcColumn::Parent => a.parent.cmp(&b.parent),
+            ProcColumn::PMem => a.p_mem.cmp(&b.p_mem),
+            ProcColumn::VMem => a.v_mem.cmp(&b.v_mem),
+            ProcColumn::RunTime => a.run_time.cmp(&b.run_time),
+            ProcColumn::Cmd => a.cmd.cmp(&b.cmd),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcSort {
+    pub column: ProcColumn,
+    pub descending: bool,
+}
+
+impl ProcSort {
+    /// picking the current column again flips the direction, a new column
+    /// starts out ascending
+    pub fn select(&mut self, column: ProcColumn) {
+        if self.column == column {
+            self.descending = !self.descending;
+        } else {
+            *self = ProcSort {
+                column,
+                descending: false,
+            };
+        }
+    }
+
+    /// ties are broken by pid so rows don't shuffle between samples
+    pub fn sort(&self, procs: &mut [&ProcMetadata]) {
+        procs.sort_by(|a, b| {
+            let ord = self.column.cmp(a, b);
+            let ord = if self.descending { ord.reverse() } else { ord };
+            ord.then_with(|| a.id.cmp(&b.id))
+        });
+    }
+}
+
+/// the selection is kept as a pid rather than a row index, so it stays on
+/// the same process when rows are added, removed or re-sorted around it
+#[derive(Debug, Default)]
+pub struct ProcTable {
+    pub sort: ProcSort,
+    pub selected: Option<Pid>,
+    /// scroll offset and highlighted row, as ratatui last drew them
+    pub state: TableState,
+    /// rows that fit on screen last frame, for page up/down
+    pub page_height: usize,
+}
+
+impl ProcTable {
+    /// `procs` in the order they're drawn
+    pub fn rows<'a>(
+        &self,
+        procs: &'a BTreeSet<ProcMetadata>,
+    ) -> Vec<&'a ProcMetadata> {
+        let mut rows: Vec<_> = procs.iter().collect();
+        self.sort.sort(&mut rows);
+        rows
+    }
+
+    /// moves the selection `offset` rows, stopping at either end. With
+    /// nothing selected this starts from the top
+    pub fn move_selection(&mut self, rows: &[Pid], offset: isize) {
+        let Some(last) = rows.len().checked_sub(1) else {
+            self.selected = None;
+            return;
+        };
+        let new_idx = match self.selected_row(rows) {
+            Some(idx) => idx.saturating_add_signed(offset).min(last),
+            None => 0,
+        };
+        self.selected = Some(rows[new_idx]);
+    }
+
+    pub fn select_first(&mut self, rows: &[Pid]) {
+        self.selected = rows.first().copied();
+    }
+
+    pub fn select_last(&mut self, rows: &[Pid]) {
+        self.selected = rows.last().copied();
+    }
+
+    fn selected_row(&self, rows: &[Pid]) -> Option<usize> {
+        let selected = self.selected?;
+        rows.iter().position(|pid| *pid == selected)
+    }
+
+    /// points the table state at the selected pid before drawing. If that
+    /// process exited, the selection moves to whatever row took its place
+    pub fn sync(&mut self, rows: &[Pid]) {
+        if self.selected.is_some() && self.selected_row(rows).is_none() {
+            self.selected = self
+                .state
+                .selected()
+                .and_then(|idx| rows.get(idx).or(rows.last()))
+                .copied();
+        }
+        self.state.select(self.selected_row(rows));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proc(pid: usize, p_mem: u64, run_time: u64) -> ProcMetadata {
+        ProcMetadata::fake(pid, "nixbld1")
+            .with_mem(p_mem, 0)
+            .with_run_time(run_time)
+    }
+
+    fn sorted(sort: ProcSort, procs: &[ProcMetadata]) -> Vec<usize> {
+        let mut procs: Vec<_> = procs.iter().collect();
+        sort.sort(&mut procs);
+        procs.iter().map(|p| usize::from(p.id)).collect()
+    }
+
+    #[test]
+    pub fn test_from_key() {
+        assert_eq!(ProcColumn::from_key('1'), Some(ProcColumn::Pid));
+        assert_eq!(ProcColumn::from_key('6'), Some(ProcColumn::RunTime));
+        assert_eq!(ProcColumn::from_key('7'), Some(ProcColumn::Cmd));
+        assert_eq!(ProcColumn::from_key('0'), None);
+        assert_eq!(ProcColumn::from_key('8'), None);
+        assert_eq!(ProcColumn::from_key('x'), None);
+    }
+
+    #[test]
+    pub fn test_sort() {
+        // 9 KiB sorts after 10 bytes, which a string sort would get wrong
+        let procs = [proc(3, 9 * 1024, 5), proc(1, 10, 100), proc(2, 10, 7)];
+        let mut sort = ProcSort::default();
+        assert_eq!(sorted(sort, &procs), vec![1, 2, 3]);
+
+        sort.select(ProcColumn::PMem);
+        assert_eq!(sort.column, ProcColumn::PMem);
+        assert!(!sort.descending);
+        assert_eq!(sorted(sort, &procs), vec![1, 2, 3]);
+        sort.select(ProcColumn::PMem);
+        assert!(sort.descending);
+        // ties stay in pid order either way
+        assert_eq!(sorted(sort, &procs), vec![3, 1, 2]);
+
+        sort.select(ProcColumn::RunTime);
+        assert!(!sort.descending);
+        assert_eq!(sorted(sort, &procs), vec![3, 2, 1]);
+    }
+
+    fn pids(pids: &[usize]) -> Vec<Pid> {
+        pids.iter().map(|pid| Pid::from(*pid)).collect()
+    }
+
+    #[test]
+    pub fn test_move_selection() {
+        let rows = pids(&[10, 20, 30]);
+        let mut table = ProcTable::default();
+        table.move_selection(&rows, 1);
+        assert_eq!(table.selected, Some(Pid::from(10)));
+        table.move_selection(&rows, 1);
+        assert_eq!(table.selected, Some(Pid::from(20)));
+        table.move_selection(&rows, 100);
+        assert_eq!(table.selected, Some(Pid::from(30)));
+        table.move_selection(&rows, -100);
+        assert_eq!(table.selected, Some(Pid::from(10)));
+        table.select_last(&rows);
+        assert_eq!(table.selected, Some(Pid::from(30)));
+        table.select_first(&rows);
+        assert_eq!(table.selected, Some(Pid::from(10)));
+        table.move_selection(&[], 1);
+        assert_eq!(table.selected, None);
+    }
+
+    #[test]
+    pub fn test_selection_follows_pid() {
+        let mut table = ProcTable {
+            selected: Some(Pid::from(20)),
+            ..Default::default()
+        };
+        table.sync(&pids(&[10, 20, 30]));
+        assert_eq!(table.state.selected(), Some(1));
+
+        // a new process sorted in above it
+        table.sync(&pids(&[5, 10, 20, 30]));
+        assert_eq!(table.state.selected(), Some(2));
+        assert_eq!(table.selected, Some(Pid::from(20)));
+
+        // it exited: stay on the same row
+        table.sync(&pids(&[5, 10, 30]));
+        assert_eq!(table.selected, Some(Pid::from(30)));
+        assert_eq!(table.state.selected(), Some(2));
+
+        // rows shrank past the selection
+        table.sync(&pids(&[5]));
+        assert_eq!(table.selected, Some(Pid::from(5)));
+
+        table.sync(&[]);
+        assert_eq!(table.selected, None);
+        assert_eq!(table.state.selected(), None);
+    }
+}
@@ -0,0 +1,290 @@
+// one TUI per terminal. Two nix-btm instances drawing to the same tty
+// interleave their escape sequences and leave it needing a `reset`, so we
+// take a lock file keyed by the controlling tty (and --session-name, for
+// people who really do want several) before touching the terminal
+use std::{
+    env,
+    ffi::CStr,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+};
+
+/// longest session name we put in a window title
+const MAX_TITLE_LEN: usize = 64;
+
+#[derive(Debug)]
+pub enum LockError {
+    /// another live nix-btm holds the lock. `pid` is None if it hasn't
+    /// written its pid yet
+    Held {
+        pid: Option<u32>,
+        tty: String,
+    },
+    Io(io::Error),
+}
+
+impl std::fmt::Display for LockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockError::Held { pid, tty } => {
+                write!(f, "nix-btm is already running on {tty}")?;
+                if let Some(pid) = pid {
+                    write!(f, " (pid {pid})")?;
+                }
+                write!(
+                    f,
+                    ". Pass --force to start anyway, or --session-name to run \
+                     another instance on purpose"
+                )
+            }
+            LockError::Io(e) => write!(f, "could not take the tty lock: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for LockError {}
+
+impl From<io::Error> for LockError {
+    fn from(e: io::Error) -> Self {
+        LockError::Io(e)
+    }
+}
+
+/// the terminal on stdin, e.g. "/dev/pts/3" or "/dev/ttys003" on macOS, if
+/// stdin is one
+pub fn controlling_tty() -> Option<String> {
+    let mut buf = [0 as libc::c_char; 256];
+    // SAFETY: ttyname_r writes at most buf.len() bytes, the NUL included
+    let ret = unsafe {
+        libc::ttyname_r(libc::STDIN_FILENO, buf.as_mut_ptr(), buf.len())
+    };
+    if ret != 0 {
+        return None;
+    }
+    // SAFETY: on success buf holds a NUL terminated path
+    let tty = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().ok()?;
+    (tty.starts_with("/dev/pts/") || tty.starts_with("/dev/tty"))
+        .then(|| tty.to_string())
+}
+
+pub fn runtime_dir() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(env::temp_dir)
+}
+
+/// keeps lock file names to `[A-Za-z0-9_-]`, whatever the tty or session
+/// name looks like
+fn sanitize_component(s: &str) -> String {
+    s.trim_matches('/')
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+/// e.g. "<dir>/nix-btm-dev-pts-3.lock", or
+/// "<dir>/nix-btm-dev-pts-3-work.lock" with a session name
+pub fn lock_path(dir: &Path, tty: &str, session: Option<&str>) -> PathBuf {
+    let mut name = format!("nix-btm-{}", sanitize_component(tty));
+    if let Some(session) = session {
+        name.push('-');
+        name.push_str(&sanitize_component(session));
+    }
+    dir.join(format!("{name}.lock"))
+}
+
+/// an exclusive flock on the lock file, held until dropped. The kernel
+/// releases it when the process exits however that happens, so a crash or
+/// a panic (which aborts) can't leave a stale lock behind
+#[derive(Debug)]
+pub struct TtyLock {
+    path: PathBuf,
+    /// None when --force started us without the lock
+    file: Option<File>,
+}
+
+impl TtyLock {
+    /// takes the lock at `path` and writes `pid` into it for the error
+    /// message others get. `force` starts anyway when someone else holds
+    /// it, without taking it from them
+    pub fn acquire(
+        path: &Path,
+        tty: &str,
+        pid: u32,
+        force: bool,
+    ) -> Result<Self, LockError> {
+        // never truncate before we hold the lock, that's the holder's pid
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if !try_flock(&file)? {
+            if force {
+                return Ok(TtyLock {
+                    path: path.to_path_buf(),
+                    file: None,
+                });
+            }
+            return Err(LockError::Held {
+                pid: read_owner(path),
+                tty: tty.to_string(),
+            });
+        }
+        file.set_len(0)?;
+        writeln!(file, "{pid}")?;
+        Ok(TtyLock {
+            path: path.to_path_buf(),
+            file: Some(file),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_held(&self) -> bool {
+        self.file.is_some()
+    }
+}
+
+// the file itself is left in place when the lock is dropped. Removing it
+// would let one instance lock the old file while another creates and locks
+// a new one
+
+/// flock(LOCK_EX | LOCK_NB). Ok(false) if someone else holds the lock
+fn try_flock(file: &File) -> io::Result<bool> {
+    // SAFETY: the fd stays open for as long as `file` is borrowed
+    let ret =
+        unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if ret == 0 {
+        return Ok(true);
+    }
+    let e = io::Error::last_os_error();
+    if e.kind() == io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(e)
+    }
+}
+
+fn read_owner(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// the session name with anything that could end or extend the escape
+/// sequence removed
+fn sanitize_title(session: &str) -> String {
+    session
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_TITLE_LEN)
+        .collect()
+}
+
+/// saves the current window title on the terminal's title stack (XTWINOPS
+/// 22) and sets a new one (OSC 0)
+pub fn set_title_sequence(session: &str) -> String {
+    format!("\x1b[22;0t\x1b]0;nix-btm: {}\x07", sanitize_title(session))
+}
+
+/// pops the title saved by `set_title_sequence`
+pub const RESTORE_TITLE_SEQUENCE: &str = "\x1b[23;0t";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a fresh directory under the system temp dir for one test
+    fn scratch_dir(test: &str) -> PathBuf {
+        let dir = env::temp_dir()
+            .join(format!("nix-btm-test-{test}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    pub fn test_lock_path() {
+        let dir = Path::new("/run/user/1000");
+        assert_eq!(
+            lock_path(dir, "/dev/pts/3", None),
+            dir.join("nix-btm-dev-pts-3.lock")
+        );
+        assert_eq!(
+            lock_path(dir, "/dev/pts/3", Some("../work/../../x")),
+            dir.join("nix-btm-dev-pts-3----work-------x.lock")
+        );
+    }
+
+    #[test]
+    pub fn test_lock_is_exclusive() {
+        let dir = scratch_dir("exclusive");
+        let path = lock_path(&dir, "/dev/pts/3", None);
+
+        let first = TtyLock::acquire(&path, "/dev/pts/3", 100, false).unwrap();
+        assert!(first.is_held());
+        assert_eq!(read_owner(&path), Some(100));
+        // a second open file description, as another process would have
+        assert!(matches!(
+            TtyLock::acquire(&path, "/dev/pts/3", 200, false),
+            Err(LockError::Held { pid: Some(100), .. })
+        ));
+
+        // --force starts without taking it over
+        let forced = TtyLock::acquire(&path, "/dev/pts/3", 200, true).unwrap();
+        assert!(!forced.is_held());
+        assert_eq!(read_owner(&path), Some(100));
+        drop(forced);
+        assert!(TtyLock::acquire(&path, "/dev/pts/3", 200, false).is_err());
+
+        // free again once the holder is gone
+        drop(first);
+        let second = TtyLock::acquire(&path, "/dev/pts/3", 200, false).unwrap();
+        assert!(second.is_held());
+        assert_eq!(read_owner(second.path()), Some(200));
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    pub fn test_leftover_lockfiles_are_reused() {
+        let dir = scratch_dir("leftover");
+        let path = lock_path(&dir, "/dev/pts/4", None);
+        // what a crashed instance leaves behind: a pid, but no flock
+        fs::write(&path, "4242\n").unwrap();
+        let lock = TtyLock::acquire(&path, "/dev/pts/4", 100, false).unwrap();
+        assert_eq!(read_owner(lock.path()), Some(100));
+
+        drop(lock);
+        fs::write(&path, "not a pid").unwrap();
+        assert!(TtyLock::acquire(&path, "/dev/pts/4", 100, false).is_ok());
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    pub fn test_title_sequences() {
+        assert_eq!(
+            set_title_sequence("work"),
+            "\x1b[22;0t\x1b]0;nix-btm: work\x07"
+        );
+        // a name can't terminate the OSC early and smuggle in its own
+        // sequences
+        let evil = set_title_sequence("a\x07\x1b]0;pwned\x1b\\b\n");
+        assert_eq!(evil, "\x1b[22;0t\x1b]0;nix-btm: a]0;pwned\\b\x07");
+        assert_eq!(
+            set_title_sequence(&"x".repeat(1000)).len(),
+            set_title_sequence("").len() + MAX_TITLE_LEN
+        );
+        assert_eq!(RESTORE_TITLE_SEQUENCE, "\x1b[23;0t");
+    }
+}
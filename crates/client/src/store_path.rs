@@ -0,0 +1,326 @@
+// validated pieces of nix store paths. Anything we pull out of /proc, log
+// file names or nix's own output goes through `StorePath::parse` before it
+// is used as a key or handed back to nix, so a malformed path is reported
+// where it's read instead of turning into a bogus `Drv` three calls later
+use std::{fmt, str::FromStr};
+
+pub const DEFAULT_STORE_DIR: &str = "/nix/store";
+
+/// nix's base32 alphabet: no e, o, u or t
+const NIX_BASE32: &[u8; 32] = b"0123456789abcdfghijklmnpqrsvwxyz";
+
+const HASH_LEN: usize = 32;
+/// longest name nix accepts
+const MAX_NAME_LEN: usize = 211;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StorePathError {
+    HashLength(usize),
+    HashChar(char),
+    EmptyName,
+    NameTooLong(usize),
+    NameChar(char),
+    NameStartsWithDot,
+    NotInStore(String),
+    MissingName(String),
+}
+
+impl fmt::Display for StorePathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorePathError::HashLength(len) => {
+                write!(f, "store hash must be {HASH_LEN} characters, got {len}")
+            }
+            StorePathError::HashChar(c) => {
+                write!(f, "{c:?} is not a nix base32 character")
+            }
+            StorePathError::EmptyName => write!(f, "store path name is empty"),
+            StorePathError::NameTooLong(len) => write!(
+                f,
+                "store path name must be at most {MAX_NAME_LEN} characters, \
+                 got {len}"
+            ),
+            StorePathError::NameChar(c) => {
+                write!(f, "{c:?} is not allowed in a store path name")
+            }
+            StorePathError::NameStartsWithDot => {
+                write!(f, "store path name may not start with '.'")
+            }
+            StorePathError::NotInStore(path) => {
+                write!(f, "{path} is not in the nix store")
+            }
+            StorePathError::MissingName(path) => {
+                write!(f, "{path} has no name after the hash")
+            }
+        }
+    }
+}
+
+impl std::error::Error for StorePathError {}
+
+/// the 32 character nix base32 digest at the start of a store path
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StoreHash(String);
+
+impl StoreHash {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for StoreHash {
+    type Err = StorePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(c) = s
+            .chars()
+            .find(|c| !c.is_ascii() || !NIX_BASE32.contains(&(*c as u8)))
+        {
+            return Err(StorePathError::HashChar(c));
+        }
+        if s.len() != HASH_LEN {
+            return Err(StorePathError::HashLength(s.len()));
+        }
+        Ok(StoreHash(s.to_string()))
+    }
+}
+
+impl fmt::Display for StoreHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// everything after the hash, e.g. "helix-24.03.drv"
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DrvName(String);
+
+impl DrvName {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl FromStr for DrvName {
+    type Err = StorePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err(StorePathError::EmptyName);
+        }
+        if s.len() > MAX_NAME_LEN {
+            return Err(StorePathError::NameTooLong(s.len()));
+        }
+        if s.starts_with('.') {
+            return Err(StorePathError::NameStartsWithDot);
+        }
+        if let Some(c) = s
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || "+-._?=".contains(*c)))
+        {
+            return Err(StorePathError::NameChar(c));
+        }
+        Ok(DrvName(s.to_string()))
+    }
+}
+
+impl fmt::Display for DrvName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StorePath {
+    pub hash: StoreHash,
+    pub name: DrvName,
+}
+
+impl StorePath {
+    /// parses "<store dir>/<hash>-<name>"
+    pub fn parse_in(
+        store_dir: &str,
+        path: &str,
+    ) -> Result<Self, StorePathError> {
+        let base = path
+            .strip_prefix(store_dir.trim_end_matches('/'))
+            .and_then(|rest| rest.strip_prefix('/'))
+            .filter(|base| !base.contains('/'))
+            .ok_or_else(|| StorePathError::NotInStore(path.to_string()))?;
+        Self::from_base_name(base)
+    }
+
+    pub fn parse(path: &str) -> Result<Self, StorePathError> {
+        Self::parse_in(DEFAULT_STORE_DIR, path)
+    }
+
+    /// parses "<hash>-<name>", the last component of a store path
+    pub fn from_base_name(base: &str) -> Result<Self, StorePathError> {
+        let (hash, name) = base
+            .split_once('-')
+            .ok_or_else(|| StorePathError::MissingName(base.to_string()))?;
+        Ok(StorePath {
+            hash: hash.parse()?,
+            name: name.parse()?,
+        })
+    }
+
+    pub fn is_drv(&self) -> bool {
+        self.name.as_str().ends_with(".drv")
+    }
+
+    /// the name without the ".drv" suffix, which is what people recognize
+    pub fn human_readable(&self) -> &str {
+        let name = self.name.as_str();
+        name.strip_suffix(".drv").unwrap_or(name)
+    }
+
+    pub fn render(&self, store_dir: &str) -> String {
+        format!(
+            "{}/{}-{}",
+            store_dir.trim_end_matches('/'),
+            self.hash,
+            self.name
+        )
+    }
+}
+
+impl FromStr for StorePath {
+    type Err = StorePathError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl fmt::Display for StorePath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.render(DEFAULT_STORE_DIR))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+    use super::*;
+
+    const NAME_CHARS: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRS\
+                                TUVWXYZ0123456789+-._?=";
+
+    fn random_hash(rng: &mut SmallRng) -> String {
+        (0..HASH_LEN)
+            .map(|_| NIX_BASE32[rng.gen_range(0..NIX_BASE32.len())] as char)
+            .collect()
+    }
+
+    fn random_name(rng: &mut SmallRng) -> String {
+        let len = rng.gen_range(1..=MAX_NAME_LEN);
+        let mut name: String = (0..len)
+            .map(|_| NAME_CHARS[rng.gen_range(0..NAME_CHARS.len())] as char)
+            .collect();
+        if name.starts_with('.') {
+            name.replace_range(0..1, "a");
+        }
+        name
+    }
+
+    #[test]
+    pub fn test_valid_paths_round_trip() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let path = format!(
+                "/nix/store/{}-{}",
+                random_hash(&mut rng),
+                random_name(&mut rng)
+            );
+            let parsed = StorePath::parse(&path).unwrap();
+            assert_eq!(parsed.to_string(), path);
+            assert_eq!(parsed.render("/nix/store/"), path);
+            assert_eq!(
+                StorePath::parse_in("/tmp/store", &parsed.render("/tmp/store")),
+                Ok(parsed)
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_invalid_hashes_are_rejected() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let hash = random_hash(&mut rng);
+            let at = rng.gen_range(0..HASH_LEN);
+            for bad in ['e', 'o', 'u', 't', 'A', 'Z', '_', 'é'] {
+                let mut corrupted = hash.clone();
+                corrupted.replace_range(at..at + 1, &bad.to_string());
+                assert_eq!(
+                    corrupted.parse::<StoreHash>(),
+                    Err(StorePathError::HashChar(bad))
+                );
+                assert!(StorePath::parse(&format!(
+                    "/nix/store/{corrupted}-name"
+                ))
+                .is_err());
+            }
+            assert_eq!(
+                hash[..at].parse::<StoreHash>(),
+                Err(StorePathError::HashLength(at))
+            );
+            assert_eq!(
+                format!("{hash}a").parse::<StoreHash>(),
+                Err(StorePathError::HashLength(HASH_LEN + 1))
+            );
+        }
+    }
+
+    #[test]
+    pub fn test_invalid_paths_are_rejected() {
+        let hash = "z4ps207hnvyh0lsrlmgkqyyfj3bbf37l";
+        for (path, err) in [
+            (
+                format!("/nix/store/{hash}"),
+                StorePathError::MissingName(hash.to_string()),
+            ),
+            (format!("/nix/store/{hash}-"), StorePathError::EmptyName),
+            (
+                format!("/nix/store/{hash}-.hidden"),
+                StorePathError::NameStartsWithDot,
+            ),
+            (
+                format!("/nix/store/{hash}-has space"),
+                StorePathError::NameChar(' '),
+            ),
+            (
+                format!("/nix/store/{hash}-{}", "a".repeat(MAX_NAME_LEN + 1)),
+                StorePathError::NameTooLong(MAX_NAME_LEN + 1),
+            ),
+            (
+                format!("/tmp/{hash}-helix"),
+                StorePathError::NotInStore(format!("/tmp/{hash}-helix")),
+            ),
+            (
+                format!("/nix/store/{hash}-helix/bin/hx"),
+                StorePathError::NotInStore(format!(
+                    "/nix/store/{hash}-helix/bin/hx"
+                )),
+            ),
+        ] {
+            assert_eq!(StorePath::parse(&path), Err(err), "{path}");
+        }
+    }
+
+    #[test]
+    pub fn test_drv_names() {
+        let drv = StorePath::parse(
+            "/nix/store/z4ps207hnvyh0lsrlmgkqyyfj3bbf37l-helix-24.03.drv",
+        )
+        .unwrap();
+        assert!(drv.is_drv());
+        assert_eq!(drv.human_readable(), "helix-24.03");
+        let out =
+            StorePath::from_base_name("z4ps207hnvyh0lsrlmgkqyyfj3bbf37l-helix")
+                .unwrap();
+        assert!(!out.is_drv());
+        assert_eq!(out.human_readable(), "helix");
+    }
+}
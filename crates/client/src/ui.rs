@@ -1,21 +1,29 @@
 use lazy_static::lazy_static;
 use ratatui::{
-    layout::{Alignment, Constraint, Layout, Rect},
+    layout::{Alignment, Constraint, Layout, Margin, Rect},
     style::{Color, Modifier, Style, Styled, Stylize},
-    text::{Line, Text},
-    widgets::{Block, Cell, Paragraph, Row, Table, TableState, Tabs, Wrap},
+    text::{Line, Span, Text},
+    widgets::{
+        Block, Cell, Clear, Paragraph, Row, Scrollbar, ScrollbarOrientation,
+        ScrollbarState, Sparkline, Table, Tabs, Wrap,
+    },
     Frame,
 };
 use strum::IntoEnumIterator;
 use tui_tree_widget::Tree;
 
 use crate::{
-    get_stats::{
-        gen_ui_by_nix_builder, get_active_users_and_pids, ProcMetadata,
-    },
+    cancel::{CancelPlan, CANCEL_GRACE_PERIOD},
+    diagnostics::{overall_status, ProbeStatus},
+    format::{format_bytes, format_duration, truncate_to_width},
+    get_stats::ProcMetadata,
     gruvbox::Gruvbox::{
         self, Dark0, OrangeBright, OrangeDim, YellowBright, YellowDim,
     },
+    names::shorten_store_paths,
+    proc_poller::BuilderSnapshot,
+    proc_table::ProcColumn,
+    utilization::bucket_samples,
     App, Pane, SelectedTab,
 };
 
@@ -50,15 +58,22 @@ lazy_static! {
         Style::default().fg(YellowDim.into());
 }
 
-const MAN_PAGE_BUILDER_VIEW: [&str; 12] = [
-    "q - QUIT",
+const MAN_PAGE_BUILDER_VIEW: [&str; 19] = [
+    "q/CTRL-C - QUIT",
     "M - TOGGLE MANUAL",
+    "1-7 - SORT BUILDER INFO BY COLUMN, AGAIN TO REVERSE",
+    "r - REFRESH NOW",
+    "x - CANCEL THE SELECTED BUILDER'S BUILD",
+    "y - COPY THE SELECTED PROCESS'S COMMAND",
+    "Y - COPY ALL BUILDERS AS TEXT",
+    "W - WRITE ALL BUILDERS TO A FILE",
     "g - SCROLL TO TOP OF BUILDER LIST",
     "G - SCROLL TO BOTTOM OF BUILDER LIST",
     "h - MOVE TO PANEL TO THE LEFT",
     "l - MOVE TO PANEL TO THE RIGHT",
-    "j - SCROLL UP BUILDER LIST",
-    "k - SCROLL DOWN BUILDER LIST ",
+    "j - SCROLL DOWN BUILDER LIST OR BUILDER INFO",
+    "k - SCROLL UP BUILDER LIST OR BUILDER INFO",
+    "PGUP/PGDN - PAGE THROUGH BUILDER INFO",
     "< - SCROLL LEFT BUILDER INFO",
     "> - SCROLL RIGHT BUILDER LIST",
     "p - PREVIOUS TAB",
@@ -66,25 +81,12 @@ const MAN_PAGE_BUILDER_VIEW: [&str; 12] = [
 ];
 
 const MAN_PAGE_BIRDS_EYE_VIEW: [&str; 4] = [
-    "q - QUIT",
+    "q/CTRL-C - QUIT",
     "M - TOGGLE MANUAL",
     "p - PREVIOUS TAB",
     "n - NEXT TAB",
 ];
 
-pub fn format_bytes(size: usize) -> String {
-    const MB: usize = 1024 * 1024;
-    const GB: usize = 1024 * 1024 * 1024; // 1024 * 1024 * 1024
-
-    if size >= GB {
-        format!("{:.3} GB", size as f64 / GB as f64)
-    } else if size >= MB {
-        format!("{:.2} MB", size as f64 / MB as f64)
-    } else {
-        format!("{} bytes", size)
-    }
-}
-
 /// borrowed from ratatui popup example
 /// helper function to create a centered rect using up certain percentage of the
 /// available rect `r`
@@ -104,6 +106,32 @@ fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     .split(popup_layout[1])[1]
 }
 
+fn draw_cancel_prompt(f: &mut Frame, size: Rect, plan: &CancelPlan) {
+    let area = centered_rect(50, 20, size);
+    let prompt = Paragraph::new(vec![
+        Line::from(format!("Cancel the build on {}?", plan.builder)),
+        Line::from(format!(
+            "This sends SIGTERM to {} processes, then SIGKILL after {}s.",
+            plan.pids.len(),
+            CANCEL_GRACE_PERIOD.as_secs()
+        )),
+        Line::from(""),
+        Line::from("y TO CONFIRM, ANY OTHER KEY TO ABORT"),
+    ])
+    .block(
+        Block::bordered()
+            .title("CANCEL BUILD")
+            .title_style(*TITLE_STYLE_SELECTED)
+            .border_style(*BORDER_STYLE_SELECTED)
+            .fg(Gruvbox::Light1)
+            .bg(Gruvbox::Dark1),
+    )
+    .alignment(Alignment::Center)
+    .wrap(Wrap { trim: true });
+    f.render_widget(Clear, area);
+    f.render_widget(prompt, area);
+}
+
 pub fn draw_man_page(f: &mut Frame, size: Rect, app: &mut App) {
     // TODO abstract out the map -> to_vec stuff
     let text = match app.tab_selected {
@@ -130,73 +158,155 @@ pub fn draw_man_page(f: &mut Frame, size: Rect, app: &mut App) {
 }
 
 pub fn draw_builder_ui(f: &mut Frame, size: Rect, app: &mut App) {
-    let user_map = get_active_users_and_pids();
-    let items = gen_ui_by_nix_builder(&user_map);
+    // never sample processes from the draw call, just render whatever the
+    // poller last published
+    let snapshot = app.builder_view.proc_poller.snapshot();
+    let user_map = &snapshot.user_map;
+    let title = if app.builder_view.proc_poller.is_updating() {
+        format!("NIX BUILDERS LIST (updating{})", app.icons.ellipsis)
+    } else {
+        "NIX BUILDERS LIST".to_string()
+    };
+    let [builders_area, utilization_area] =
+        Layout::vertical([Constraint::Min(0), Constraint::Length(6)])
+            .areas(size);
     let chunks = Layout::horizontal([
         // title
         Constraint::Percentage(20),
         //content
         Constraint::Percentage(80),
     ])
-    .split(size);
+    .split(builders_area);
 
-    let widget = Tree::new(&items)
-        .expect("all item identifiers are unique")
-        .block(
-            Block::bordered()
-                .title("NIX BUILDERS LIST")
-                .title_bottom("")
-                .title_style(app.builder_view.gen_title_style(Pane::Left))
-                .border_style(app.builder_view.gen_border_style(Pane::Left))
-                .bg(Gruvbox::Dark1)
-                .fg(Gruvbox::Light1),
-        )
-        .highlight_style(
-            Style::new()
-                .fg(Dark0.into())
-                .bg(if app.builder_view.selected_pane == Pane::Left {
-                    OrangeBright.into()
-                } else {
-                    OrangeDim.into()
-                })
-                .add_modifier(Modifier::BOLD),
-        )
-        .highlight_symbol("> ");
-    f.render_stateful_widget(widget, chunks[0], &mut app.builder_view.state);
-
-    let mut table_state = TableState::default();
-    let header = ["pid", "env", "parent pid", "p_mem", "v_mem", "⏰", "cmd"]
-        .into_iter()
-        .map(Cell::from)
-        .collect::<Row>();
-    let mut rows = Vec::new();
-    if let Some(selected) = app.builder_view.state.selected().first() {
-        for ProcMetadata {
-            id,
-            env,
-            parent,
-            p_mem,
-            v_mem,
-            run_time,
-            cmd,
-            owner: _name,
-        } in user_map.get(selected).unwrap().iter()
-        {
-            rows.push(
-                [
-                    &id.to_string(),
-                    &env.to_vec().join(" "),
-                    &(*parent).unwrap().to_string(),
-                    &format_bytes(*p_mem as usize),
-                    &format_bytes(*v_mem as usize),
-                    &format!("{}s", run_time),
-                    &cmd.iter().take(8).cloned().collect::<Vec<_>>().join(" "),
-                ]
-                .into_iter()
-                .map(|content| Cell::from(Text::from(content.to_string())))
-                .collect::<Row>(),
-            )
+    let block = Block::bordered()
+        .title(title)
+        .title_bottom(truncate_to_width(
+            app.builder_view
+                .status_message
+                .as_deref()
+                .unwrap_or_default(),
+            chunks[0].width.saturating_sub(2) as usize,
+        ))
+        .title_style(app.builder_view.gen_title_style(Pane::Left))
+        .border_style(app.builder_view.gen_border_style(Pane::Left))
+        .bg(Gruvbox::Dark1)
+        .fg(Gruvbox::Light1);
+    if snapshot.items.is_empty() {
+        f.render_widget(
+            Paragraph::new("no nix builders active")
+                .block(block)
+                .wrap(Wrap { trim: true }),
+            chunks[0],
+        );
+    } else {
+        match Tree::new(&snapshot.items) {
+            Ok(widget) => {
+                let widget = widget
+                    .block(block)
+                    .highlight_style(
+                        Style::new()
+                            .fg(Dark0.into())
+                            .bg(
+                                if app.builder_view.selected_pane == Pane::Left
+                                {
+                                    OrangeBright.into()
+                                } else {
+                                    OrangeDim.into()
+                                },
+                            )
+                            .add_modifier(Modifier::BOLD),
+                    )
+                    .highlight_symbol("> ");
+                f.render_stateful_widget(
+                    widget,
+                    chunks[0],
+                    &mut app.builder_view.state,
+                );
+            }
+            // duplicate identifiers; not worth taking the terminal down over
+            Err(e) => f.render_widget(
+                Paragraph::new(format!("can't draw builders: {e}"))
+                    .block(block)
+                    .wrap(Wrap { trim: true }),
+                chunks[0],
+            ),
+        }
+    }
+
+    let sort = app.builder_view.proc_table.sort;
+    let arrow = if sort.descending {
+        app.icons.sort_descending
+    } else {
+        app.icons.sort_ascending
+    };
+    let header = [
+        "pid",
+        "env",
+        "parent pid",
+        "p_mem",
+        "v_mem",
+        app.icons.run_time,
+        "cmd",
+    ]
+    .into_iter()
+    .zip(ProcColumn::ALL)
+    .map(|(name, column)| {
+        if column == sort.column {
+            Cell::from(format!("{name}{arrow}"))
+        } else {
+            Cell::from(name)
         }
+    })
+    .collect::<Row>();
+    let mut rows = Vec::new();
+    // the selection can outlive the builder it points at, e.g. when the
+    // builder's last process exits between samples
+    let selected_procs = app
+        .builder_view
+        .state
+        .selected()
+        .first()
+        .and_then(|selected| user_map.get(selected));
+    let procs = selected_procs
+        .map(|procs| app.builder_view.proc_table.rows(procs))
+        .unwrap_or_default();
+    let pids: Vec<_> = procs.iter().map(|p| p.id).collect();
+    app.builder_view.proc_table.sync(&pids);
+    let selected_proc = app
+        .builder_view
+        .proc_table
+        .selected
+        .and_then(|pid| procs.iter().find(|p| p.id == pid));
+    for ProcMetadata {
+        id,
+        env,
+        parent,
+        p_mem,
+        v_mem,
+        run_time,
+        cmd,
+        owner: _name,
+        start_time: _,
+    } in procs.iter().copied()
+    {
+        rows.push(
+            [
+                &id.to_string(),
+                &env.to_vec().join(" "),
+                &parent.map(|p| p.to_string()).unwrap_or_default(),
+                &format_bytes(*p_mem),
+                &format_bytes(*v_mem),
+                &format_duration(*run_time),
+                &cmd.iter()
+                    .take(8)
+                    .map(|arg| shorten_store_paths(arg))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ]
+            .into_iter()
+            .map(|content| Cell::from(Text::from(content.to_string())))
+            .collect::<Row>(),
+        )
     }
 
     let widths = [
@@ -235,6 +345,14 @@ pub fn draw_builder_ui(f: &mut Frame, size: Rect, app: &mut App) {
             _ => 100,
         }),
     ];
+    let [table_area, detail_area] = Layout::vertical([
+        Constraint::Min(0),
+        Constraint::Length(if selected_proc.is_some() { 4 } else { 0 }),
+    ])
+    .areas(chunks[1]);
+    // borders and the header row
+    app.builder_view.proc_table.page_height =
+        table_area.height.saturating_sub(3) as usize;
     let table = Table::new(rows, widths)
         .header(header)
         .block(
@@ -246,11 +364,105 @@ pub fn draw_builder_ui(f: &mut Frame, size: Rect, app: &mut App) {
                 .bg(Gruvbox::Dark1)
                 .fg(Gruvbox::Light3),
         )
-        .highlight_style(Style::new().fg(Gruvbox::Light3.into()));
-    f.render_stateful_widget(table, chunks[1], &mut table_state);
+        .highlight_style(Style::new().fg(Dark0.into()).bg(
+            if app.builder_view.selected_pane == Pane::Right {
+                OrangeBright.into()
+            } else {
+                OrangeDim.into()
+            },
+        ));
+    f.render_stateful_widget(
+        table,
+        table_area,
+        &mut app.builder_view.proc_table.state,
+    );
+    let mut scrollbar_state = ScrollbarState::new(pids.len())
+        .position(app.builder_view.proc_table.state.selected().unwrap_or(0));
+    f.render_stateful_widget(
+        Scrollbar::new(ScrollbarOrientation::VerticalRight),
+        table_area.inner(Margin {
+            vertical: 1,
+            horizontal: 0,
+        }),
+        &mut scrollbar_state,
+    );
+    if let Some(proc) = selected_proc {
+        // the table cuts commands off, so show the whole thing here
+        f.render_widget(
+            Paragraph::new(proc.cmd.join(" "))
+                .block(
+                    Block::bordered()
+                        .title(format!("PROCESS {}", proc.id))
+                        .title_style(*TITLE_STYLE_UNSELECTED)
+                        .border_style(*BORDER_STYLE_UNSELECTED)
+                        .bg(Gruvbox::Dark1)
+                        .fg(Gruvbox::Light3),
+                )
+                .wrap(Wrap { trim: false }),
+            detail_area,
+        );
+    }
+
+    draw_utilization(f, utilization_area, &snapshot);
 }
 
-pub fn render_title(f: &mut Frame, area: Rect, s: &str) {
+fn draw_utilization(f: &mut Frame, area: Rect, snapshot: &BuilderSnapshot) {
+    let active = snapshot.utilization.latest().unwrap_or(0);
+    let slots = match snapshot.nix_config.max_jobs {
+        Some(max_jobs) => format!("{active}/{max_jobs} SLOTS BUSY"),
+        None => format!("{active} SLOTS BUSY"),
+    };
+    let title = truncate_to_width(
+        &format!(
+            "BUILDER UTILIZATION {slots}, LOAD {:.1}",
+            snapshot.load_average
+        ),
+        area.width.saturating_sub(2) as usize,
+    );
+    let data = bucket_samples(
+        snapshot.utilization.samples(),
+        area.width.saturating_sub(2) as usize,
+    );
+    // scale against the configured limit so a full bar means every slot is
+    // busy, falling back to the number of nixbld users
+    let max = snapshot
+        .nix_config
+        .max_jobs
+        .unwrap_or(snapshot.user_map.len())
+        .max(1) as u64;
+    let sparkline = Sparkline::default()
+        .block(
+            Block::bordered()
+                .title(title)
+                .title_style(*TITLE_STYLE_UNSELECTED)
+                .border_style(*BORDER_STYLE_UNSELECTED)
+                .bg(Gruvbox::Dark1),
+        )
+        .data(&data)
+        .max(max)
+        .style(Style::new().fg(Gruvbox::AquaBright.into()));
+    f.render_widget(sparkline, area);
+}
+
+/// `s` followed by the health indicator, once the first probes are in
+pub fn title_line<'a>(s: &'a str, app: &App) -> Line<'a> {
+    let color = match overall_status(&app.health.latest()) {
+        None => return Line::from(s),
+        Some(ProbeStatus::Pass) => Gruvbox::GreenBright,
+        Some(ProbeStatus::Warn) => Gruvbox::YellowBright,
+        Some(ProbeStatus::Fail) => Gruvbox::RedBright,
+    };
+    if app.icons.health.is_empty() {
+        return Line::from(s);
+    }
+    Line::from(vec![
+        Span::raw(s),
+        Span::raw(" "),
+        Span::styled(app.icons.health, Style::new().fg(color.into())),
+    ])
+}
+
+pub fn render_title(f: &mut Frame, area: Rect, s: Line) {
     f.render_widget(
         Paragraph::new(s)
             .bold()
@@ -267,7 +479,7 @@ pub fn render_tab(f: &mut Frame, area: Rect, app: &mut App) {
     let tab_style: (Color, Color) =
         (Gruvbox::Light3.into(), Gruvbox::Dark1.into());
     let titles = SelectedTab::iter()
-        .map(SelectedTab::title)
+        .map(|tab| tab.title(&app.icons))
         .map(|x| x.style(Style::new().bg(Gruvbox::Dark3.into())));
 
     let selected_tab_index = app.tab_selected as usize;
@@ -292,17 +504,20 @@ pub fn ui(f: &mut Frame, app: &mut App) {
 
     match app.tab_selected {
         SelectedTab::BuilderView => {
-            render_title(f, title_area, "Builder View");
+            render_title(f, title_area, title_line("Builder View", app));
             render_tab(f, tabs_area, app);
             if app.builder_view.man_toggle {
                 draw_man_page(f, inner_area, app);
             } else {
-                draw_builder_ui(f, inner_area, app)
+                draw_builder_ui(f, inner_area, app);
+                if let Some(plan) = &app.builder_view.pending_cancel {
+                    draw_cancel_prompt(f, inner_area, plan);
+                }
             }
         }
         SelectedTab::BirdsEyeView => {
             render_tab(f, tabs_area, app);
-            render_title(f, title_area, "Birds Eye View");
+            render_title(f, title_area, title_line("Birds Eye View", app));
             if app.birds_eye_view.man_toggle {
                 draw_man_page(f, inner_area, app);
             } else {
@@ -315,3 +530,222 @@ pub fn ui(f: &mut Frame, app: &mut App) {
 fn draw_birds_eye_ui(f: &mut Frame, inner_area: Rect, app: &mut App) {
     // todo!()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use ratatui::{backend::TestBackend, buffer::Buffer, Terminal};
+
+    use crate::{
+        get_stats::ProcMetadata,
+        icons::IconSet,
+        proc_poller::{BuilderSnapshot, ProcPoller},
+        App, BuilderViewState,
+    };
+
+    fn fake_user_map(
+        num_users: usize,
+        procs_per_user: usize,
+    ) -> HashMap<String, BTreeSet<ProcMetadata>> {
+        (1..=num_users)
+            .map(|user_num| {
+                let owner = format!("nixbld{user_num}");
+                let procs = (0..procs_per_user)
+                    .map(|i| {
+                        ProcMetadata::fake(
+                            user_num * procs_per_user + i,
+                            &owner,
+                        )
+                        .with_parent(1)
+                        .with_mem(1024, 2048)
+                        .with_run_time(10)
+                        .with_cmd(&["bash", "-e"])
+                    })
+                    .collect();
+                (owner, procs)
+            })
+            .collect()
+    }
+
+    #[test]
+    pub fn test_draw_does_not_wait_on_tree_generation() {
+        use tui_tree_widget::TreeItem;
+
+        // the tree in the snapshot doesn't match its user map. The draw
+        // showing it anyway means ui() took what the poller built instead of
+        // building its own
+        let snapshot = BuilderSnapshot {
+            items: vec![TreeItem::new_leaf(
+                "nixbld1".to_string(),
+                "prebuilt tree",
+            )],
+            ..BuilderSnapshot::new(fake_user_map(32, 320))
+        };
+        let proc_poller = ProcPoller::default();
+        proc_poller.publish(snapshot);
+        let mut app = App {
+            builder_view: BuilderViewState {
+                proc_poller,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        app.builder_view.state.select(vec!["nixbld1".to_string()]);
+        let mut terminal = Terminal::new(TestBackend::new(200, 60)).unwrap();
+        terminal.draw(|f| super::ui(f, &mut app)).unwrap();
+        let buffer = terminal.backend().buffer();
+        assert!(find(buffer, "prebuilt tree").is_some());
+        assert!(find(buffer, "nixbld2").is_none());
+    }
+
+    fn find(buffer: &Buffer, needle: &str) -> Option<(u16, u16)> {
+        let area = buffer.area;
+        (area.top()..area.bottom()).find_map(|y| {
+            (area.left()..area.right())
+                .find(|&x| {
+                    let mut rest = needle;
+                    let mut x = x;
+                    while !rest.is_empty() && x < area.right() {
+                        let Some(tail) =
+                            rest.strip_prefix(buffer.get(x, y).symbol())
+                        else {
+                            return false;
+                        };
+                        rest = tail;
+                        x += 1;
+                    }
+                    rest.is_empty()
+                })
+                .map(|x| (x, y))
+        })
+    }
+
+    #[test]
+    pub fn test_icon_sets_keep_table_aligned() {
+        let positions: Vec<_> =
+            [IconSet::emoji(), IconSet::ascii(), IconSet::none()]
+                .into_iter()
+                .map(|icons| {
+                    let proc_poller = ProcPoller::default();
+                    proc_poller
+                        .publish(BuilderSnapshot::new(fake_user_map(2, 3)));
+                    let mut app = App {
+                        builder_view: BuilderViewState {
+                            proc_poller,
+                            ..Default::default()
+                        },
+                        icons,
+                        ..Default::default()
+                    };
+                    app.builder_view.state.select(vec!["nixbld1".to_string()]);
+                    let mut terminal =
+                        Terminal::new(TestBackend::new(200, 40)).unwrap();
+                    terminal.draw(|f| super::ui(f, &mut app)).unwrap();
+                    let buffer = terminal.backend().buffer();
+                    (
+                        find(buffer, icons.run_time).unwrap(),
+                        find(buffer, "cmd").unwrap(),
+                        find(buffer, "bash -e").unwrap(),
+                    )
+                })
+                .collect();
+        assert!(positions.windows(2).all(|w| w[0] == w[1]), "{positions:?}");
+    }
+
+    #[test]
+    pub fn test_no_builders_placeholder() {
+        let proc_poller = ProcPoller::default();
+        proc_poller.publish(BuilderSnapshot::new(HashMap::new()));
+        let mut app = App {
+            builder_view: BuilderViewState {
+                proc_poller,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // a stale selection must not panic either
+        app.builder_view.state.select(vec!["nixbld1".to_string()]);
+        let mut terminal = Terminal::new(TestBackend::new(120, 40)).unwrap();
+        terminal.draw(|f| super::ui(f, &mut app)).unwrap();
+        assert!(find(terminal.backend().buffer(), "no nix builders").is_some());
+    }
+
+    /// one builder running a process per name, with the name in its
+    /// command line (and environment, if `in_env`)
+    fn named_procs(
+        names: &[&str],
+        in_env: bool,
+    ) -> HashMap<String, BTreeSet<ProcMetadata>> {
+        let mut user_map = fake_user_map(1, names.len());
+        let procs = user_map.remove("nixbld1").unwrap();
+        let procs = procs
+            .into_iter()
+            .zip(names)
+            .map(|(proc, name)| {
+                let env = format!("name={name}");
+                let proc = proc.with_cmd(&[name, name]);
+                if in_env {
+                    proc.with_env(&[&env])
+                } else {
+                    proc
+                }
+            })
+            .collect();
+        user_map.insert("nixbld1".to_string(), procs);
+        user_map
+    }
+
+    fn render(
+        user_map: HashMap<String, BTreeSet<ProcMetadata>>,
+        status_message: Option<String>,
+        width: u16,
+    ) -> Buffer {
+        let proc_poller = ProcPoller::default();
+        proc_poller.publish(BuilderSnapshot::new(user_map));
+        let mut app = App {
+            builder_view: BuilderViewState {
+                proc_poller,
+                status_message,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        app.builder_view.state.select(vec!["nixbld1".to_string()]);
+        let mut terminal = Terminal::new(TestBackend::new(width, 30)).unwrap();
+        terminal.draw(|f| super::ui(f, &mut app)).unwrap();
+        terminal.backend().buffer().clone()
+    }
+
+    #[test]
+    pub fn test_adversarial_names_render() {
+        let names = [
+            "python3.11-日本語パッケージ-1.0",
+            "👨\u{200d}👩\u{200d}👧-family-2.0",
+            "cafe\u{301}-zalgo-t\u{337}\u{330}e\u{334}xt",
+            "שלום-עולם-3.1",
+        ];
+        let plain = ["a", "b", "c", "d"];
+        for width in [20, 41, 80, 133] {
+            // nothing panics, even with the names in the status line
+            render(named_procs(&names, true), Some(names.concat()), width);
+
+            // and the names don't push any other cell around: everything
+            // left of the cmd column matches a render with plain names
+            let adversarial = render(named_procs(&names, false), None, width);
+            let baseline = render(named_procs(&plain, false), None, width);
+            let (cmd_x, _) = find(&baseline, "cmd").unwrap_or_else(|| {
+                panic!("no cmd column to align against at width {width}")
+            });
+            for y in 0..baseline.area.height {
+                for x in 0..cmd_x {
+                    assert_eq!(
+                        adversarial.get(x, y).symbol(),
+                        baseline.get(x, y).symbol(),
+                        "({x}, {y}) at width {width}"
+                    );
+                }
+            }
+        }
+    }
+}
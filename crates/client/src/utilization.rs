@@ -0,0 +1,174 @@
+// answers "are we actually using all of our build slots?" by comparing the
+// number of nixbld users with live processes against nix's max-jobs
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    thread,
+};
+
+use crate::{get_stats::ProcMetadata, nix_cli::nix_fresh};
+
+/// ten minutes at the default poll interval
+pub const UTILIZATION_HISTORY_LEN: usize = 600;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NixBuildConfig {
+    pub max_jobs: Option<usize>,
+    pub cores: Option<usize>,
+}
+
+/// parses the `key = value` lines printed by `nix config show`
+pub fn parse_nix_config(output: &str) -> NixBuildConfig {
+    let mut config = NixBuildConfig::default();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match (key.trim(), value.trim()) {
+            ("max-jobs", "auto") => {
+                config.max_jobs =
+                    thread::available_parallelism().ok().map(usize::from);
+            }
+            ("max-jobs", value) => config.max_jobs = value.parse().ok(),
+            ("cores", value) => config.cores = value.parse().ok(),
+            _ => {}
+        }
+    }
+    config
+}
+
+/// nix may not be on the PATH, or may predate `nix config show`, in which
+/// case we just don't know the limits. Only read at startup and on `r`, so
+/// an earlier failure never stands in for asking again
+pub fn read_nix_build_config() -> NixBuildConfig {
+    let output = nix_fresh(&["config", "show"])
+        .or_else(|_| nix_fresh(&["show-config"]))
+        .ok();
+    match output {
+        Some(output) => {
+            parse_nix_config(&String::from_utf8_lossy(&output.stdout))
+        }
+        None => NixBuildConfig::default(),
+    }
+}
+
+pub fn count_active_builders(
+    user_map: &HashMap<String, BTreeSet<ProcMetadata>>,
+) -> u64 {
+    user_map.values().filter(|procs| !procs.is_empty()).count() as u64
+}
+
+/// bounded record of how many builders were busy at each poll
+#[derive(Debug, Clone)]
+pub struct UtilizationHistory {
+    samples: VecDeque<u64>,
+    capacity: usize,
+}
+
+impl Default for UtilizationHistory {
+    fn default() -> Self {
+        UtilizationHistory::with_capacity(UTILIZATION_HISTORY_LEN)
+    }
+}
+
+impl UtilizationHistory {
+    pub fn with_capacity(capacity: usize) -> Self {
+        UtilizationHistory {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, active: u64) {
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(active);
+    }
+
+    pub fn latest(&self) -> Option<u64> {
+        self.samples.back().copied()
+    }
+
+    pub fn samples(&self) -> impl ExactSizeIterator<Item = u64> + '_ {
+        self.samples.iter().copied()
+    }
+}
+
+/// squeeze `samples` (oldest first) into at most `width` columns. Each column
+/// takes the max of its bucket so that short bursts stay visible
+pub fn bucket_samples(
+    samples: impl ExactSizeIterator<Item = u64>,
+    width: usize,
+) -> Vec<u64> {
+    if width == 0 {
+        return vec![];
+    }
+    let bucket_size = samples.len().div_ceil(width).max(1);
+    let samples: Vec<u64> = samples.collect();
+    samples
+        .chunks(bucket_size)
+        .map(|bucket| bucket.iter().copied().max().unwrap_or(0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeSet, HashMap};
+
+    use super::*;
+
+    #[test]
+    pub fn test_parse_nix_config() {
+        let output = "\
+accept-flake-config = false
+cores = 8
+max-jobs = 16
+substituters = https://cache.nixos.org/
+";
+        assert_eq!(
+            parse_nix_config(output),
+            NixBuildConfig {
+                max_jobs: Some(16),
+                cores: Some(8),
+            }
+        );
+        assert_eq!(parse_nix_config(""), NixBuildConfig::default());
+        assert_eq!(
+            parse_nix_config("max-jobs = auto").max_jobs,
+            thread::available_parallelism().ok().map(usize::from)
+        );
+        assert_eq!(parse_nix_config("cores = lots").cores, None);
+    }
+
+    #[test]
+    pub fn test_history_is_bounded() {
+        let mut history = UtilizationHistory::with_capacity(3);
+        assert_eq!(history.latest(), None);
+        for i in 0..5 {
+            history.push(i);
+        }
+        assert_eq!(history.samples().collect::<Vec<_>>(), vec![2, 3, 4]);
+        assert_eq!(history.latest(), Some(4));
+    }
+
+    #[test]
+    pub fn test_bucket_samples() {
+        let samples = [1, 5, 2, 2, 0, 7, 3];
+        assert_eq!(bucket_samples(samples.into_iter(), 10), samples);
+        assert_eq!(bucket_samples(samples.into_iter(), 4), vec![5, 2, 7, 3]);
+        assert_eq!(bucket_samples(samples.into_iter(), 1), vec![7]);
+        assert!(bucket_samples(samples.into_iter(), 0).is_empty());
+        assert!(bucket_samples([].into_iter(), 5).is_empty());
+    }
+
+    #[test]
+    pub fn test_count_active_builders() {
+        let proc = ProcMetadata::fake(42, "nixbld1");
+        let user_map = HashMap::from([
+            ("nixbld1".to_string(), BTreeSet::from([proc])),
+            ("nixbld2".to_string(), BTreeSet::new()),
+        ]);
+        assert_eq!(count_active_builders(&user_map), 1);
+        assert_eq!(count_active_builders(&HashMap::new()), 0);
+    }
+}